@@ -0,0 +1,391 @@
+//! Aggregation of the results over days, months and (hydrological) years.
+//!
+//! Every row of the results is stamped with the start of its `saveat` period. Rates are
+//! means over that period, so a row that straddles the boundary of an aggregation period
+//! counts for both, weighted by the overlap. States like the storage and level are values
+//! at the time stamp, and belong to the aggregation period that contains it.
+//!
+//! The `realized` flow of the allocation results is the exception: it is the mean over
+//! the period that ends at the time stamp. It is moved to the row that starts that
+//! period, so the realized flow at the start time, which has no period, is left out.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arrow::{
+    array::{ArrayRef, Float64Array, RecordBatch, TimestampMillisecondArray, UInt32Array},
+    compute::take,
+    datatypes::{DataType, Field, Schema},
+};
+use chrono::{Datelike, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use clap::ValueEnum;
+use ribasim::{
+    config::Config,
+    error::Error,
+    results::{self, key_columns, period_lengths, row_keys, Table, RESULTS_FILES},
+};
+
+/// The periods to aggregate over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Period {
+    Day,
+    Month,
+    Year,
+    /// A year starting at the first of `--hydro-year-start`
+    HydroYear,
+}
+
+impl Period {
+    fn name(self) -> &'static str {
+        match self {
+            Period::Day => "day",
+            Period::Month => "month",
+            Period::Year => "year",
+            Period::HydroYear => "hydro_year",
+        }
+    }
+
+    /// The start of the period that contains the given time.
+    fn start(self, time: NaiveDateTime, hydro_year_start: u32) -> NaiveDateTime {
+        let date = time.date();
+        let start = match self {
+            Period::Day => Some(date),
+            Period::Month => date.with_day(1),
+            Period::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1),
+            Period::HydroYear => {
+                let year = if date.month() >= hydro_year_start {
+                    date.year()
+                } else {
+                    date.year() - 1
+                };
+                NaiveDate::from_ymd_opt(year, hydro_year_start, 1)
+            }
+        };
+        start
+            .expect("the first day of a month exists")
+            .and_time(NaiveTime::MIN)
+    }
+
+    /// The start of the next period.
+    fn next(self, start: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            Period::Day => start + TimeDelta::days(1),
+            Period::Month => start + Months::new(1),
+            Period::Year | Period::HydroYear => start + Months::new(12),
+        }
+    }
+}
+
+/// The statistic of each aggregation period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Stat {
+    /// The mean, weighted by duration for rates
+    Mean,
+    /// The volume in m³ of rates, states are left out
+    Sum,
+    Min,
+    Max,
+}
+
+impl Stat {
+    fn name(self) -> &'static str {
+        match self {
+            Stat::Mean => "mean",
+            Stat::Sum => "sum",
+            Stat::Min => "min",
+            Stat::Max => "max",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// A flow rate in m³/s, averaged over the `saveat` period.
+    Rate,
+    /// Averaged over the `saveat` period, but not a rate that adds up to a volume.
+    Mean,
+    /// A value at the time stamp.
+    State,
+}
+
+fn kind(column: &str) -> Kind {
+    match column {
+        "storage" | "level" | "subgrid_level" => Kind::State,
+        "inflow_rate" | "outflow_rate" | "storage_rate" | "precipitation" | "evaporation"
+        | "drainage" | "infiltration" | "balance_error" | "flow_rate" | "demand" | "allocated"
+        | "realized" => Kind::Rate,
+        _ => Kind::Mean,
+    }
+}
+
+/// The name of the volume column that sums a rate column.
+fn volume_name(column: &str) -> String {
+    if column == "storage_rate" {
+        return "storage_change".to_string();
+    }
+    match column.strip_suffix("_rate") {
+        Some(stem) => format!("{stem}_volume"),
+        None => format!("{column}_volume"),
+    }
+}
+
+/// The part of a results row that falls within an aggregation period.
+struct Segment {
+    row: usize,
+    /// The overlap in seconds.
+    weight: f64,
+    /// Whether the time stamp of the row is within the period.
+    sampled: bool,
+}
+
+struct Group {
+    start: NaiveDateTime,
+    /// The first row, to take the key values from.
+    first: usize,
+    segments: Vec<Segment>,
+}
+
+/// Aggregate the given results tables, or all that exist, and write them as Arrow files
+/// named like `basin_month_mean.arrow` to the output directory, which defaults to the
+/// `results_dir`. Returns the paths that were written.
+pub fn aggregate(
+    config: &Config,
+    tables: &[String],
+    period: Period,
+    stat: Stat,
+    hydro_year_start: u32,
+    output: Option<&Path>,
+) -> Result<Vec<PathBuf>, Error> {
+    if tables.iter().any(|name| name == "control") {
+        return Err(Error::InvalidArgument(
+            "the control results have no values to aggregate".to_string(),
+        ));
+    }
+    let output = output.map_or_else(|| config.results_path(""), Path::to_owned);
+    let names: Vec<&str> = if tables.is_empty() {
+        RESULTS_FILES
+            .iter()
+            .map(|(name, _)| *name)
+            .filter(|name| *name != "control")
+            .collect()
+    } else {
+        tables.iter().map(String::as_str).collect()
+    };
+
+    let mut written = Vec::new();
+    for name in names {
+        let table = match Table::read_results(config, name) {
+            Ok(table) => table,
+            Err(Error::ResultsNotFound(_)) if tables.is_empty() => continue,
+            Err(err) => return Err(err),
+        };
+        let Some(batch) =
+            aggregate_table(&table, name, config.endtime, period, stat, hydro_year_start)?
+        else {
+            if tables.is_empty() {
+                continue;
+            }
+            return Err(Error::InvalidArgument(format!(
+                "the {name} results have no values to take the {} of",
+                stat.name()
+            )));
+        };
+        let path = output.join(format!("{name}_{}_{}.arrow", period.name(), stat.name()));
+        results::write(&batch, config.results.arrow_compression(), &path).map_err(|source| {
+            Error::Write {
+                path: path.clone(),
+                source,
+            }
+        })?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Aggregate a results table, or `None` if it has no columns for the statistic.
+fn aggregate_table(
+    table: &Table,
+    name: &str,
+    endtime: NaiveDateTime,
+    period: Period,
+    stat: Stat,
+    hydro_year_start: u32,
+) -> Result<Option<RecordBatch>, Error> {
+    let arrow_error = |source| Error::Arrow {
+        path: table.path.clone(),
+        source,
+    };
+    let time = table.times("time")?;
+    let dt = period_lengths(&time, endtime);
+    let keys: Vec<&str> = key_columns(name)
+        .iter()
+        .copied()
+        .filter(|key| *key != "time")
+        .collect();
+    let row_keys = row_keys(table, &keys)?;
+
+    let mut groups: Vec<Group> = Vec::new();
+    let mut index: HashMap<(NaiveDateTime, &str), usize> = HashMap::new();
+    for row in 0..table.num_rows() {
+        let begin = time[row];
+        let end = begin + TimeDelta::milliseconds((dt[row] * 1000.0).round() as i64);
+        let mut start = period.start(begin, hydro_year_start);
+        loop {
+            let next = period.next(start);
+            let overlap = end.min(next) - begin.max(start);
+            let group = *index.entry((start, &row_keys[row])).or_insert_with(|| {
+                groups.push(Group {
+                    start,
+                    first: row,
+                    segments: Vec::new(),
+                });
+                groups.len() - 1
+            });
+            groups[group].segments.push(Segment {
+                row,
+                weight: overlap.num_milliseconds() as f64 / 1000.0,
+                sampled: begin >= start,
+            });
+            if end <= next {
+                break;
+            }
+            start = next;
+        }
+    }
+    groups.sort_by_key(|group| (group.start, group.first));
+
+    let mut fields = vec![Field::new(
+        "time",
+        DataType::Timestamp(arrow::datatypes::TimeUnit::Millisecond, None),
+        false,
+    )];
+    let mut columns: Vec<ArrayRef> = vec![Arc::new(TimestampMillisecondArray::from(
+        groups
+            .iter()
+            .map(|group| group.start.and_utc().timestamp_millis())
+            .collect::<Vec<_>>(),
+    ))];
+
+    let first = UInt32Array::from(
+        groups
+            .iter()
+            .map(|group| group.first as u32)
+            .collect::<Vec<_>>(),
+    );
+    let schema = table.batch.schema();
+    for field in schema.fields() {
+        let name = field.name().as_str();
+        if name == "time" {
+            continue;
+        }
+        if keys.contains(&name) {
+            let column = table
+                .batch
+                .column_by_name(name)
+                .expect("field of the schema");
+            columns.push(take(column, &first, None).map_err(arrow_error)?);
+            fields.push(field.as_ref().clone());
+            continue;
+        }
+        if field.data_type() != &DataType::Float64 {
+            continue;
+        }
+        let kind = kind(name);
+        let name = match (kind, stat) {
+            (Kind::Rate, Stat::Sum) => volume_name(name),
+            (_, Stat::Sum) => continue,
+            _ => name.to_string(),
+        };
+        let mut values: Vec<Option<f64>> =
+            table.f64s(field.name())?.into_iter().map(Some).collect();
+        if field.name() == "realized" {
+            values = shift_back(&values, &time, &row_keys);
+        }
+        let aggregated: Float64Array = groups
+            .iter()
+            .map(|group| reduce(&values, &group.segments, kind, stat))
+            .collect();
+        columns.push(Arc::new(aggregated));
+        fields.push(Field::new(name, DataType::Float64, true));
+    }
+
+    if fields.len() == keys.len() + 1 {
+        return Ok(None);
+    }
+
+    let mut metadata = HashMap::from([
+        ("period".to_string(), period.name().to_string()),
+        ("stat".to_string(), stat.name().to_string()),
+    ]);
+    if let Some(version) = table.metadata("ribasim_version") {
+        metadata.insert("ribasim_version".to_string(), version.to_string());
+    }
+    RecordBatch::try_new(
+        Arc::new(Schema::new_with_metadata(fields, metadata)),
+        columns,
+    )
+    .map(Some)
+    .map_err(arrow_error)
+}
+
+/// Give each row the value of the next row with the same key, and the last row none.
+fn shift_back(
+    values: &[Option<f64>],
+    time: &[NaiveDateTime],
+    row_keys: &[String],
+) -> Vec<Option<f64>> {
+    let mut series: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (row, key) in row_keys.iter().enumerate() {
+        series.entry(key).or_default().push(row);
+    }
+    let mut shifted = vec![None; values.len()];
+    for rows in series.values_mut() {
+        rows.sort_by_key(|row| time[*row]);
+        for pair in rows.windows(2) {
+            shifted[pair[0]] = values[pair[1]];
+        }
+    }
+    shifted
+}
+
+/// The statistic of a column over the segments of a group, or `None` if no row
+/// contributes to it.
+fn reduce(values: &[Option<f64>], segments: &[Segment], kind: Kind, stat: Stat) -> Option<f64> {
+    if kind == Kind::State {
+        let samples = segments
+            .iter()
+            .filter(|segment| segment.sampled)
+            .filter_map(|segment| values[segment.row]);
+        return match stat {
+            Stat::Mean => {
+                let (sum, count) =
+                    samples.fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+                (count > 0).then(|| sum / count as f64)
+            }
+            Stat::Min => samples.reduce(f64::min),
+            Stat::Max => samples.reduce(f64::max),
+            Stat::Sum => unreachable!("states are left out of sums"),
+        };
+    }
+
+    let weighted = segments
+        .iter()
+        .filter(|segment| segment.weight > 0.0)
+        .filter_map(|segment| Some((values[segment.row]?, segment.weight)));
+    match stat {
+        Stat::Mean => {
+            let (sum, duration) = weighted.fold((0.0, 0.0), |(sum, duration), (value, weight)| {
+                (sum + value * weight, duration + weight)
+            });
+            (duration > 0.0).then(|| sum / duration)
+        }
+        Stat::Sum => weighted
+            .map(|(value, weight)| value * weight)
+            .reduce(|a, b| a + b),
+        Stat::Min => weighted.map(|(value, _)| value).reduce(f64::min),
+        Stat::Max => weighted.map(|(value, _)| value).reduce(f64::max),
+    }
+}
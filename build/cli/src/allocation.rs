@@ -0,0 +1,237 @@
+//! Shortage report of the demands in the allocation results.
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDateTime;
+use ribasim::{config::Config, error::Error, results::Table};
+use serde::Serialize;
+
+use crate::table;
+
+/// Realized flows below this fraction of the demand don't count as a shortage, to
+/// ignore solver noise.
+const SHORTAGE_RTOL: f64 = 1e-6;
+
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+/// The shortage of a UserDemand, or of a Basin with a LevelDemand, for one priority.
+#[derive(Debug, Serialize)]
+pub struct DemandShortage {
+    pub subnetwork_id: i32,
+    pub node_type: String,
+    pub node_id: i32,
+    pub priority: i32,
+    /// Volumes in m³.
+    pub demand: f64,
+    pub shortage: f64,
+    /// The time in days with a shortage.
+    pub shortage_days: f64,
+    /// The longest uninterrupted shortage in days, and when it started.
+    pub longest_spell_days: f64,
+    pub longest_spell_start: Option<NaiveDateTime>,
+    /// Absent if there is no demand.
+    pub fraction_met: Option<f64>,
+}
+
+/// The shortages of all demands in a subnetwork.
+#[derive(Debug, Serialize)]
+pub struct SubnetworkShortage {
+    pub subnetwork_id: i32,
+    pub demand: f64,
+    pub shortage: f64,
+    pub fraction_met: Option<f64>,
+    /// The number of demands, by node and priority, with any shortage.
+    pub demands_short: usize,
+    pub demands: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AllocationReport {
+    pub demands: Vec<DemandShortage>,
+    pub subnetworks: Vec<SubnetworkShortage>,
+}
+
+/// A running spell of shortage.
+#[derive(Default)]
+struct Spell {
+    start: Option<NaiveDateTime>,
+    days: f64,
+}
+
+impl AllocationReport {
+    /// Integrate the demands and realized flows of allocation.arrow over time.
+    ///
+    /// The demand at a time is for the allocation interval that starts then, while the
+    /// realized flow is the mean over the interval that ends then. So each demand is
+    /// compared with the realized flow of the next row of the same node and priority, and
+    /// the demand of the last interval, which has no realized flow yet, is left out.
+    /// LevelDemand nodes are listed under the Basins they act on.
+    pub fn from_results(config: &Config) -> Result<AllocationReport, Error> {
+        let allocation = Table::read_results(config, "allocation")?;
+        let time = allocation.times("time")?;
+        let subnetwork_id = allocation.i32s("subnetwork_id")?;
+        let node_type = allocation.strings("node_type")?;
+        let node_id = allocation.i32s("node_id")?;
+        let priority = allocation.i32s("priority")?;
+        let demand = allocation.f64s("demand")?;
+        let realized = allocation.f64s("realized")?;
+
+        // The rows of each demand, in order of time
+        let mut series: BTreeMap<(i32, String, i32, i32), Vec<usize>> = BTreeMap::new();
+        for i in 0..allocation.num_rows() {
+            let (Some(subnetwork_id), Some(node_type), Some(node_id), Some(priority)) =
+                (subnetwork_id[i], &node_type[i], node_id[i], priority[i])
+            else {
+                continue;
+            };
+            if !matches!(node_type.as_str(), "UserDemand" | "Basin" | "LevelDemand") {
+                continue;
+            }
+            series
+                .entry((subnetwork_id, node_type.clone(), node_id, priority))
+                .or_default()
+                .push(i);
+        }
+
+        let mut demands = Vec::new();
+        for ((subnetwork_id, node_type, node_id, priority), mut rows) in series {
+            rows.sort_by_key(|&i| time[i]);
+            let mut shortage = DemandShortage {
+                subnetwork_id,
+                node_type,
+                node_id,
+                priority,
+                demand: 0.0,
+                shortage: 0.0,
+                shortage_days: 0.0,
+                longest_spell_days: 0.0,
+                longest_spell_start: None,
+                fraction_met: None,
+            };
+            let mut spell = Spell::default();
+            for pair in rows.windows(2) {
+                let (i, next) = (pair[0], pair[1]);
+                let dt = (time[next] - time[i]).num_milliseconds() as f64 / 1000.0;
+                // Negative values of Basins are a surplus, not a demand
+                let demand = demand[i].max(0.0);
+                let short = demand - realized[next];
+                shortage.demand += demand * dt;
+                if short > SHORTAGE_RTOL * demand {
+                    shortage.shortage += short * dt;
+                    shortage.shortage_days += dt / SECONDS_PER_DAY;
+                    spell.start.get_or_insert(time[i]);
+                    spell.days += dt / SECONDS_PER_DAY;
+                    if spell.days > shortage.longest_spell_days {
+                        shortage.longest_spell_days = spell.days;
+                        shortage.longest_spell_start = spell.start;
+                    }
+                } else {
+                    spell = Spell::default();
+                }
+            }
+            shortage.fraction_met = fraction_met(shortage.demand, shortage.shortage);
+            demands.push(shortage);
+        }
+
+        let mut subnetworks: BTreeMap<i32, SubnetworkShortage> = BTreeMap::new();
+        for shortage in &demands {
+            let subnetwork = subnetworks
+                .entry(shortage.subnetwork_id)
+                .or_insert_with(|| SubnetworkShortage {
+                    subnetwork_id: shortage.subnetwork_id,
+                    demand: 0.0,
+                    shortage: 0.0,
+                    fraction_met: None,
+                    demands_short: 0,
+                    demands: 0,
+                });
+            subnetwork.demand += shortage.demand;
+            subnetwork.shortage += shortage.shortage;
+            subnetwork.demands += 1;
+            if shortage.shortage > 0.0 {
+                subnetwork.demands_short += 1;
+            }
+        }
+        for subnetwork in subnetworks.values_mut() {
+            subnetwork.fraction_met = fraction_met(subnetwork.demand, subnetwork.shortage);
+        }
+
+        Ok(AllocationReport {
+            demands,
+            subnetworks: subnetworks.into_values().collect(),
+        })
+    }
+
+    pub fn print(&self) {
+        println!("Allocation shortages, volumes in m³ and durations in days");
+        println!();
+        let rows: Vec<Vec<String>> = self
+            .demands
+            .iter()
+            .map(|shortage| {
+                vec![
+                    shortage.subnetwork_id.to_string(),
+                    shortage.node_type.clone(),
+                    shortage.node_id.to_string(),
+                    shortage.priority.to_string(),
+                    table::number(shortage.demand),
+                    table::number(shortage.shortage),
+                    table::number(shortage.shortage_days),
+                    table::number(shortage.longest_spell_days),
+                    shortage
+                        .longest_spell_start
+                        .map_or_else(String::new, |start| start.to_string()),
+                    optional_number(shortage.fraction_met),
+                ]
+            })
+            .collect();
+        table::print(
+            &[
+                "subnetwork_id",
+                "node_type",
+                "node_id",
+                "priority",
+                "demand",
+                "shortage",
+                "shortage_days",
+                "longest_spell",
+                "spell_start",
+                "fraction_met",
+            ],
+            &rows,
+        );
+
+        println!();
+        let rows: Vec<Vec<String>> = self
+            .subnetworks
+            .iter()
+            .map(|subnetwork| {
+                vec![
+                    subnetwork.subnetwork_id.to_string(),
+                    table::number(subnetwork.demand),
+                    table::number(subnetwork.shortage),
+                    optional_number(subnetwork.fraction_met),
+                    format!("{}/{}", subnetwork.demands_short, subnetwork.demands),
+                ]
+            })
+            .collect();
+        table::print(
+            &[
+                "subnetwork_id",
+                "demand",
+                "shortage",
+                "fraction_met",
+                "demands_short",
+            ],
+            &rows,
+        );
+    }
+}
+
+fn fraction_met(demand: f64, shortage: f64) -> Option<f64> {
+    (demand > 0.0).then(|| 1.0 - shortage / demand)
+}
+
+fn optional_number(value: Option<f64>) -> String {
+    value.map_or_else(|| "-".to_string(), table::number)
+}
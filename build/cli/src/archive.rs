@@ -0,0 +1,352 @@
+//! Portable archives of a model, with the files in a fixed layout and a manifest of
+//! SHA-256 checksums.
+//!
+//! The archive holds exactly the files that the TOML refers to:
+//!
+//! ```text
+//! ribasim.toml
+//! input/database.gpkg
+//! input/basin/time.arrow
+//! manifest.json
+//! ```
+//!
+//! The paths in the TOML are rewritten to this layout, so the model runs wherever it is
+//! unpacked.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use ribasim::{
+    config::{Config, ConfigError},
+    error::Error,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use toml_edit::DocumentMut;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+pub const MANIFEST: &str = "manifest.json";
+const INPUT_DIR: &str = "input";
+const RESULTS_DIR: &str = "results";
+const DATABASE: &str = "database.gpkg";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The `ribasim_version` of the TOML.
+    pub ribasim_version: String,
+    /// The name of the TOML file in the archive.
+    pub toml: String,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// The path in the archive, with `/` as separator.
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Zip,
+    TarZst,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Result<Format, Error> {
+        let name = path.to_string_lossy();
+        if name.ends_with(".zip") {
+            Ok(Format::Zip)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Ok(Format::TarZst)
+        } else {
+            Err(Error::InvalidArgument(format!(
+                "{}: expected an archive ending in .zip or .tar.zst",
+                path.display()
+            )))
+        }
+    }
+}
+
+/// The content of a file in the archive.
+enum Source {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+/// Pack the TOML, the database and the Arrow input files of a model into a zip or
+/// tar.zst archive, depending on the extension of `output`.
+pub fn pack(toml_path: &Path, output: &Path) -> Result<Manifest, Error> {
+    let format = Format::from_path(output)?;
+    let config = Config::from_path(toml_path)?;
+
+    let mut entries = vec![(format!("{INPUT_DIR}/{DATABASE}"), config.database_path())];
+    for (section, paths) in &config.tables {
+        for (kind, path) in paths {
+            entries.push((
+                format!("{INPUT_DIR}/{section}/{kind}.arrow"),
+                config.input_path(path),
+            ));
+        }
+    }
+    for (_, path) in &entries {
+        if !path.is_file() {
+            return Err(Error::InvalidArchive {
+                path: path.clone(),
+                message: format!(
+                    "file not found, it is referenced by {}",
+                    toml_path.display()
+                ),
+            });
+        }
+    }
+
+    let toml_name = toml_path.file_name().map_or_else(
+        || "ribasim.toml".to_string(),
+        |name| name.to_string_lossy().to_string(),
+    );
+    let toml = rewrite_toml(toml_path, &config)?;
+    let mut sources = vec![(toml_name.clone(), Source::Bytes(toml.into_bytes()))];
+    sources.extend(
+        entries
+            .into_iter()
+            .map(|(name, path)| (name, Source::File(path))),
+    );
+
+    let mut files = Vec::new();
+    for (name, source) in &sources {
+        let (size, sha256) = match source {
+            Source::File(path) => {
+                let mut file = File::open(path).map_err(|err| read_error(path, err))?;
+                checksum(&mut file).map_err(|err| read_error(path, err))?
+            }
+            Source::Bytes(bytes) => checksum(&mut bytes.as_slice()).expect("reading bytes"),
+        };
+        files.push(ManifestFile {
+            path: name.clone(),
+            size,
+            sha256,
+        });
+    }
+    let manifest = Manifest {
+        ribasim_version: config.ribasim_version.clone(),
+        toml: toml_name,
+        files,
+    };
+    sources.push((
+        MANIFEST.to_string(),
+        Source::Bytes(
+            serde_json::to_vec_pretty(&manifest).expect("the manifest serializes to JSON"),
+        ),
+    ));
+
+    if let Some(dir) = output.parent() {
+        fs::create_dir_all(dir).map_err(|err| write_error(output, err))?;
+    }
+    let result = match format {
+        Format::Zip => write_zip(output, &sources),
+        Format::TarZst => write_tar_zst(output, &sources),
+    };
+    result.map_err(|err| write_error(output, err))?;
+    Ok(manifest)
+}
+
+/// Unpack an archive into `dir` and verify the files against the manifest. Returns the
+/// path of the TOML file and the manifest.
+pub fn unpack(archive: &Path, dir: &Path) -> Result<(PathBuf, Manifest), Error> {
+    let format = Format::from_path(archive)?;
+    if dir
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        return Err(Error::InvalidArgument(format!(
+            "{} is not empty",
+            dir.display()
+        )));
+    }
+    fs::create_dir_all(dir).map_err(|err| write_error(dir, err))?;
+    match format {
+        Format::Zip => extract_zip(archive, dir)?,
+        Format::TarZst => {
+            let file = File::open(archive).map_err(|err| read_error(archive, err))?;
+            let decoder = zstd::Decoder::new(file).map_err(|err| read_error(archive, err))?;
+            // Entries that would end up outside of dir are skipped
+            tar::Archive::new(decoder)
+                .unpack(dir)
+                .map_err(|err| read_error(archive, err))?;
+        }
+    }
+
+    let manifest_path = dir.join(MANIFEST);
+    let manifest: Manifest = fs::read(&manifest_path)
+        .map_err(|err| read_error(&manifest_path, err))
+        .and_then(|bytes| {
+            serde_json::from_slice(&bytes).map_err(|err| Error::InvalidArchive {
+                path: manifest_path.clone(),
+                message: err.to_string(),
+            })
+        })?;
+    // The manifest comes from the archive, so its paths must not leave dir either
+    let mut outside: Vec<&str> = manifest
+        .files
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .chain([manifest.toml.as_str()])
+        .filter(|path| !is_enclosed(path))
+        .collect();
+    outside.sort_unstable();
+    outside.dedup();
+    if !outside.is_empty() {
+        return Err(Error::InvalidArchive {
+            path: manifest_path,
+            message: format!(
+                "{} would be read from outside of the target directory",
+                outside.join(", ")
+            ),
+        });
+    }
+
+    let mut problems = Vec::new();
+    for entry in &manifest.files {
+        let path = dir.join(&entry.path);
+        match File::open(&path).and_then(|mut file| checksum(&mut file)) {
+            Ok((_, sha256)) if sha256 == entry.sha256 => {}
+            Ok(_) => problems.push(format!("{} does not match its checksum", entry.path)),
+            Err(_) => problems.push(format!("{} is missing", entry.path)),
+        }
+    }
+    if !problems.is_empty() {
+        return Err(Error::InvalidArchive {
+            path: archive.to_owned(),
+            message: problems.join(", "),
+        });
+    }
+
+    let toml_path = dir.join(&manifest.toml);
+    Config::from_path(&toml_path)?;
+    Ok((toml_path, manifest))
+}
+
+/// The TOML with its paths pointing to the layout of the archive, keeping the rest of
+/// the file intact.
+fn rewrite_toml(toml_path: &Path, config: &Config) -> Result<String, Error> {
+    let text =
+        fs::read_to_string(toml_path).map_err(|err| ConfigError::Io(toml_path.to_owned(), err))?;
+    let mut document: DocumentMut = text.parse().map_err(|err| Error::InvalidArchive {
+        path: toml_path.to_owned(),
+        message: format!("{err}"),
+    })?;
+    document["input_dir"] = toml_edit::value(INPUT_DIR);
+    document["results_dir"] = toml_edit::value(RESULTS_DIR);
+    document["database"] = toml_edit::value(DATABASE);
+    for (section, paths) in &config.tables {
+        for kind in paths.keys() {
+            document[section.as_str()][kind.as_str()] =
+                toml_edit::value(format!("{section}/{kind}.arrow"));
+        }
+    }
+    Ok(document.to_string())
+}
+
+/// Whether a relative path stays below the directory it is joined to, like the names
+/// of zip entries that `enclosed_name` accepts.
+fn is_enclosed(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// The size and the hexadecimal SHA-256 checksum of the contents of a reader.
+fn checksum(reader: &mut impl Read) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let size = io::copy(reader, &mut hasher)?;
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+fn write_zip(output: &Path, sources: &[(String, Source)]) -> io::Result<()> {
+    let mut writer = ZipWriter::new(File::create(output)?);
+    for (name, source) in sources {
+        let size = match source {
+            Source::File(path) => fs::metadata(path)?.len(),
+            Source::Bytes(bytes) => bytes.len() as u64,
+        };
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .large_file(size >= u32::MAX as u64);
+        writer.start_file(name.as_str(), options)?;
+        match source {
+            Source::File(path) => {
+                io::copy(&mut File::open(path)?, &mut writer)?;
+            }
+            Source::Bytes(bytes) => writer.write_all(bytes)?,
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn write_tar_zst(output: &Path, sources: &[(String, Source)]) -> io::Result<()> {
+    let encoder = zstd::Encoder::new(File::create(output)?, 0)?;
+    let mut builder = tar::Builder::new(encoder);
+    for (name, source) in sources {
+        match source {
+            Source::File(path) => builder.append_path_with_name(path, name)?,
+            Source::Bytes(bytes) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(bytes.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder.append_data(&mut header, name, bytes.as_slice())?;
+            }
+        }
+    }
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn extract_zip(archive: &Path, dir: &Path) -> Result<(), Error> {
+    let invalid = |message: String| Error::InvalidArchive {
+        path: archive.to_owned(),
+        message,
+    };
+    let file = File::open(archive).map_err(|err| read_error(archive, err))?;
+    let mut zip = ZipArchive::new(file).map_err(|err| invalid(err.to_string()))?;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(|err| invalid(err.to_string()))?;
+        let Some(name) = entry.enclosed_name() else {
+            return Err(invalid(format!(
+                "{} would be written outside of the target directory",
+                entry.name()
+            )));
+        };
+        let path = dir.join(name);
+        if entry.is_dir() {
+            fs::create_dir_all(&path).map_err(|err| write_error(&path, err))?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| write_error(parent, err))?;
+        }
+        let mut out = File::create(&path).map_err(|err| write_error(&path, err))?;
+        io::copy(&mut entry, &mut out).map_err(|err| write_error(&path, err))?;
+    }
+    Ok(())
+}
+
+fn read_error(path: &Path, err: io::Error) -> Error {
+    Error::InvalidArchive {
+        path: path.to_owned(),
+        message: err.to_string(),
+    }
+}
+
+fn write_error(path: &Path, err: io::Error) -> Error {
+    Error::Write {
+        path: path.to_owned(),
+        source: err.into(),
+    }
+}
@@ -0,0 +1,282 @@
+//! A safe wrapper over the Basic Model Interface (BMI) exported by libribasim.
+//!
+//! ```no_run
+//! use ribasim::{bmi::Model, library::LibRibasim};
+//!
+//! let lib = LibRibasim::load(None, false)?;
+//! let mut model = Model::initialize(&lib, "ribasim.toml".as_ref())?;
+//! while model.current_time()? < model.end_time()? {
+//!     model.update()?;
+//!     let storage = model.value("basin.storage")?;
+//!     println!("{storage:?}");
+//! }
+//! model.finalize()?;
+//! # Ok::<(), ribasim::error::Error>(())
+//! ```
+
+use std::{
+    ffi::{CStr, CString},
+    marker::PhantomData,
+    path::Path,
+    slice,
+};
+
+use libc::{c_char, c_double, c_int, c_void};
+use libloading::Symbol;
+
+use crate::{
+    error::Error,
+    library::{path_to_cstring, LibRibasim},
+};
+
+/// Buffer size for variable types, the same as `BMI_LENVARTYPE` in ribasim_api.
+const LEN_VAR_TYPE: usize = 51;
+
+/// Buffer size for error messages. libribasim does not bound the length of the message it
+/// writes, so this is chosen much larger than `BMI_LENERRMESSAGE` in ribasim_api.
+const LEN_ERROR_MESSAGE: usize = 65536;
+
+/// A Ribasim model, driven through libribasim's BMI functions.
+///
+/// libribasim holds a single global model, so only one `Model` can exist at a time. The
+/// model is finalized when dropped, which writes the results. Use [`Model::finalize`] to
+/// handle any error that occurs while doing so.
+pub struct Model<'lib> {
+    lib: &'lib LibRibasim,
+    finalized: bool,
+    // Julia may only be called from the thread that initialized it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'lib> Model<'lib> {
+    /// Initialize a model from the path to a TOML file, starting Julia if needed.
+    pub fn initialize(lib: &'lib LibRibasim, toml_path: &Path) -> Result<Model<'lib>, Error> {
+        lib.init_julia()?;
+        if lib.model_active.replace(true) {
+            return Err(Error::ModelActive);
+        }
+        let mut model = Model {
+            lib,
+            finalized: false,
+            _not_send: PhantomData,
+        };
+        let path = path_to_cstring(toml_path)?;
+        let result = unsafe {
+            let initialize: Symbol<unsafe extern "C" fn(*const c_char) -> c_int> =
+                lib.get("initialize")?;
+            initialize(path.as_ptr())
+        };
+        if let Err(err) = model.check("initialize", result) {
+            // Nothing to finalize, but the global model slot must be released
+            model.finalized = true;
+            lib.model_active.set(false);
+            return Err(err);
+        }
+        Ok(model)
+    }
+
+    /// Perform a single internal time step.
+    pub fn update(&mut self) -> Result<(), Error> {
+        let result = unsafe {
+            let update: Symbol<unsafe extern "C" fn() -> c_int> = self.lib.get("update")?;
+            update()
+        };
+        self.check("update", result)
+    }
+
+    /// Take internal time steps until the given time in seconds since the start.
+    pub fn update_until(&mut self, time: f64) -> Result<(), Error> {
+        let result = unsafe {
+            let update_until: Symbol<unsafe extern "C" fn(c_double) -> c_int> =
+                self.lib.get("update_until")?;
+            update_until(time)
+        };
+        self.check("update_until", result)
+    }
+
+    /// Compute the subgrid levels for the current basin levels.
+    pub fn update_subgrid_level(&mut self) -> Result<(), Error> {
+        let result = unsafe {
+            let update_subgrid_level: Symbol<unsafe extern "C" fn() -> c_int> =
+                self.lib.get("update_subgrid_level")?;
+            update_subgrid_level()
+        };
+        self.check("update_subgrid_level", result)
+    }
+
+    /// Finalize the model, which writes the results.
+    pub fn finalize(mut self) -> Result<(), Error> {
+        self.finalize_inner()
+    }
+
+    /// The current time in seconds since the start of the simulation.
+    pub fn current_time(&self) -> Result<f64, Error> {
+        self.get_time("get_current_time")
+    }
+
+    /// The start time in seconds, which is always 0.
+    pub fn start_time(&self) -> Result<f64, Error> {
+        self.get_time("get_start_time")
+    }
+
+    /// The end time in seconds since the start of the simulation.
+    pub fn end_time(&self) -> Result<f64, Error> {
+        self.get_time("get_end_time")
+    }
+
+    /// The proposed size of the next internal time step in seconds.
+    pub fn time_step(&self) -> Result<f64, Error> {
+        self.get_time("get_time_step")
+    }
+
+    /// The C type of a variable, e.g. "double".
+    pub fn var_type(&self, name: &str) -> Result<String, Error> {
+        let name_c = name_to_cstring(name)?;
+        let mut buffer = [0 as c_char; LEN_VAR_TYPE];
+        let result = unsafe {
+            let get_var_type: Symbol<unsafe extern "C" fn(*const c_char, *mut c_char) -> c_int> =
+                self.lib.get("get_var_type")?;
+            get_var_type(name_c.as_ptr(), buffer.as_mut_ptr())
+        };
+        self.check("get_var_type", result)?;
+        Ok(buffer_to_string(&buffer))
+    }
+
+    /// The number of dimensions of a variable.
+    pub fn var_rank(&self, name: &str) -> Result<usize, Error> {
+        let name_c = name_to_cstring(name)?;
+        let mut rank: c_int = 0;
+        let result = unsafe {
+            let get_var_rank: Symbol<unsafe extern "C" fn(*const c_char, *mut c_int) -> c_int> =
+                self.lib.get("get_var_rank")?;
+            get_var_rank(name_c.as_ptr(), &mut rank)
+        };
+        self.check("get_var_rank", result)?;
+        Ok(rank as usize)
+    }
+
+    /// The size of each dimension of a variable, in Julia's column-major order.
+    pub fn var_shape(&self, name: &str) -> Result<Vec<usize>, Error> {
+        let rank = self.var_rank(name)?;
+        let name_c = name_to_cstring(name)?;
+        let mut shape: Vec<c_int> = vec![0; rank];
+        let result = unsafe {
+            let get_var_shape: Symbol<unsafe extern "C" fn(*const c_char, *mut c_int) -> c_int> =
+                self.lib.get("get_var_shape")?;
+            get_var_shape(name_c.as_ptr(), shape.as_mut_ptr())
+        };
+        self.check("get_var_shape", result)?;
+        Ok(shape.into_iter().map(|n| n as usize).collect())
+    }
+
+    /// A view of the values of a variable, e.g. "basin.level".
+    ///
+    /// Multi-dimensional variables such as "user_demand.demand" are flattened in
+    /// column-major order, see [`Model::var_shape`].
+    pub fn value(&self, name: &str) -> Result<&[f64], Error> {
+        let (ptr, len) = self.value_ptr(name)?;
+        // The slice borrows the model, so it cannot outlive the next update
+        Ok(unsafe { slice::from_raw_parts(ptr, len) })
+    }
+
+    /// A mutable view of the values of a variable, e.g. "basin.drainage".
+    ///
+    /// Only the variables that are documented as writable are taken into account by
+    /// the simulation.
+    pub fn value_mut(&mut self, name: &str) -> Result<&mut [f64], Error> {
+        let (ptr, len) = self.value_ptr(name)?;
+        Ok(unsafe { slice::from_raw_parts_mut(ptr, len) })
+    }
+
+    fn value_ptr(&self, name: &str) -> Result<(*mut f64, usize), Error> {
+        let var_type = self.var_type(name)?;
+        if var_type != "double" {
+            return Err(Error::UnsupportedVarType {
+                name: name.to_string(),
+                var_type,
+            });
+        }
+        let len = self.var_shape(name)?.iter().product();
+        let name_c = name_to_cstring(name)?;
+        let mut ptr: *mut c_void = std::ptr::null_mut();
+        let result = unsafe {
+            let get_value_ptr: Symbol<
+                unsafe extern "C" fn(*const c_char, *mut *mut c_void) -> c_int,
+            > = self.lib.get("get_value_ptr")?;
+            get_value_ptr(name_c.as_ptr(), &mut ptr)
+        };
+        self.check("get_value_ptr", result)?;
+        if ptr.is_null() {
+            // An empty Julia array may not have allocated any memory
+            return Ok((std::ptr::NonNull::dangling().as_ptr(), 0));
+        }
+        Ok((ptr.cast(), len))
+    }
+
+    fn get_time(&self, function: &'static str) -> Result<f64, Error> {
+        let mut time: c_double = 0.0;
+        let result = unsafe {
+            let get_time: Symbol<unsafe extern "C" fn(*mut c_double) -> c_int> =
+                self.lib.get(function)?;
+            get_time(&mut time)
+        };
+        self.check(function, result)?;
+        Ok(time)
+    }
+
+    fn finalize_inner(&mut self) -> Result<(), Error> {
+        if self.finalized {
+            return Ok(());
+        }
+        self.finalized = true;
+        self.lib.model_active.set(false);
+        let result = unsafe {
+            let finalize: Symbol<unsafe extern "C" fn() -> c_int> = self.lib.get("finalize")?;
+            finalize()
+        };
+        self.check("finalize", result)
+    }
+
+    /// Turn the return code of a BMI function into an error with the message from
+    /// `get_last_bmi_error`.
+    fn check(&self, function: &'static str, result: c_int) -> Result<(), Error> {
+        if result == 0 {
+            return Ok(());
+        }
+        let mut buffer = vec![0 as c_char; LEN_ERROR_MESSAGE];
+        let message = unsafe {
+            match self
+                .lib
+                .get::<unsafe extern "C" fn(*mut c_char) -> c_int>("get_last_bmi_error")
+            {
+                Ok(get_last_bmi_error) if get_last_bmi_error(buffer.as_mut_ptr()) == 0 => {
+                    buffer_to_string(&buffer)
+                }
+                _ => "no error message available".to_string(),
+            }
+        };
+        Err(Error::Bmi { function, message })
+    }
+}
+
+impl Drop for Model<'_> {
+    fn drop(&mut self) {
+        // Errors can't be returned from drop, use `finalize` to handle them
+        let _ = self.finalize_inner();
+    }
+}
+
+fn name_to_cstring(name: &str) -> Result<CString, Error> {
+    CString::new(name).map_err(|_| Error::InvalidVarName(name.to_string()))
+}
+
+fn buffer_to_string(buffer: &[c_char]) -> String {
+    // Always NUL terminate, in case the library filled the whole buffer
+    let mut bytes: Vec<u8> = buffer.iter().map(|&c| c as u8).collect();
+    if let Some(last) = bytes.last_mut() {
+        *last = 0;
+    }
+    CStr::from_bytes_until_nul(&bytes)
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
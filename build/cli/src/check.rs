@@ -0,0 +1,607 @@
+//! Network topology checks of the Node and Edge tables, mirroring validation.jl.
+//!
+//! The Julia core only runs these checks after it has started and read the whole model.
+//! Here they run directly on the GeoPackage, and all violations are collected. Tables
+//! that are read from Arrow files instead of the database are not checked.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use ribasim::{
+    config::Config,
+    error::Error,
+    geopackage::{Database, Edge, Node},
+};
+use serde::Serialize;
+
+/// A broken rule, named after the function in validation.jl that checks it.
+#[derive(Debug, Serialize)]
+pub struct Violation {
+    pub check: &'static str,
+    pub message: String,
+}
+
+/// Allowed types of the downstream node of an edge, given the type of the upstream node,
+/// like `neighbortypes`.
+const NEIGHBOR_TYPES: [(&str, &[&str]); 15] = [
+    (
+        "Basin",
+        &[
+            "LinearResistance",
+            "TabulatedRatingCurve",
+            "ManningResistance",
+            "Pump",
+            "Outlet",
+            "UserDemand",
+        ],
+    ),
+    (
+        "DiscreteControl",
+        &[
+            "Pump",
+            "Outlet",
+            "TabulatedRatingCurve",
+            "LinearResistance",
+            "ManningResistance",
+            "FractionalFlow",
+            "PidControl",
+        ],
+    ),
+    (
+        "FlowBoundary",
+        &["Basin", "FractionalFlow", "Terminal", "LevelBoundary"],
+    ),
+    (
+        "FlowDemand",
+        &[
+            "LinearResistance",
+            "ManningResistance",
+            "TabulatedRatingCurve",
+            "Pump",
+            "Outlet",
+        ],
+    ),
+    ("FractionalFlow", &["Basin", "Terminal", "LevelBoundary"]),
+    (
+        "LevelBoundary",
+        &["LinearResistance", "Pump", "Outlet", "TabulatedRatingCurve"],
+    ),
+    ("LevelDemand", &["Basin"]),
+    ("LinearResistance", &["Basin", "LevelBoundary"]),
+    ("ManningResistance", &["Basin"]),
+    (
+        "Outlet",
+        &["Basin", "FractionalFlow", "Terminal", "LevelBoundary"],
+    ),
+    ("PidControl", &["Pump", "Outlet"]),
+    (
+        "Pump",
+        &["Basin", "FractionalFlow", "Terminal", "LevelBoundary"],
+    ),
+    (
+        "TabulatedRatingCurve",
+        &["Basin", "FractionalFlow", "Terminal", "LevelBoundary"],
+    ),
+    ("Terminal", &[]),
+    (
+        "UserDemand",
+        &["Basin", "FractionalFlow", "Terminal", "LevelBoundary"],
+    ),
+];
+
+/// Allowed number of in- and outneighbors, like `n_neighbor_bounds`.
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    in_min: usize,
+    in_max: usize,
+    out_min: usize,
+    out_max: usize,
+}
+
+const fn bounds(in_min: usize, in_max: usize, out_min: usize, out_max: usize) -> Bounds {
+    Bounds {
+        in_min,
+        in_max,
+        out_min,
+        out_max,
+    }
+}
+
+const MANY: usize = usize::MAX;
+
+/// The bounds over flow edges and over control edges per node type, like
+/// `n_neighbor_bounds_flow` and `n_neighbor_bounds_control`.
+const NEIGHBOR_BOUNDS: [(&str, Bounds, Bounds); 15] = [
+    ("Basin", bounds(0, MANY, 0, MANY), bounds(0, 1, 0, 0)),
+    ("DiscreteControl", bounds(0, 0, 0, 0), bounds(0, 0, 1, MANY)),
+    ("FlowBoundary", bounds(0, 0, 1, MANY), bounds(0, 0, 0, 0)),
+    ("FlowDemand", bounds(0, 0, 0, 0), bounds(0, 0, 1, 1)),
+    ("FractionalFlow", bounds(1, 1, 1, 1), bounds(0, 1, 0, 0)),
+    (
+        "LevelBoundary",
+        bounds(0, MANY, 0, MANY),
+        bounds(0, 0, 0, 0),
+    ),
+    ("LevelDemand", bounds(0, 0, 0, 0), bounds(0, 0, 1, MANY)),
+    ("LinearResistance", bounds(1, 1, 1, 1), bounds(0, 1, 0, 0)),
+    ("ManningResistance", bounds(1, 1, 1, 1), bounds(0, 1, 0, 0)),
+    ("Outlet", bounds(1, 1, 1, 1), bounds(0, 1, 0, 0)),
+    ("PidControl", bounds(0, 0, 0, 0), bounds(0, 1, 1, 1)),
+    ("Pump", bounds(1, 1, 1, MANY), bounds(0, 1, 0, 0)),
+    (
+        "TabulatedRatingCurve",
+        bounds(1, 1, 1, MANY),
+        bounds(0, 1, 0, 0),
+    ),
+    ("Terminal", bounds(1, MANY, 0, 0), bounds(0, 0, 0, 0)),
+    ("UserDemand", bounds(1, 1, 1, 1), bounds(0, 0, 0, 0)),
+];
+
+const EDGE_TYPES: [&str; 2] = ["flow", "control"];
+
+/// A node, identified by its type and ID like `NodeID`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct NodeId<'a> {
+    node_type: &'a str,
+    node_id: i32,
+}
+
+impl std::fmt::Display for NodeId<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} #{}", self.node_type, self.node_id)
+    }
+}
+
+/// The nodes and the edges between them, leaving out edges that refer to missing nodes
+/// or have an invalid type.
+struct Graph<'a> {
+    nodes: BTreeMap<NodeId<'a>, &'a Node>,
+    /// Outneighbors and inneighbors per node and edge type.
+    out: HashMap<(NodeId<'a>, &'a str), Vec<NodeId<'a>>>,
+    into: HashMap<(NodeId<'a>, &'a str), Vec<NodeId<'a>>>,
+    edges: Vec<(&'a Edge, NodeId<'a>, NodeId<'a>)>,
+}
+
+impl<'a> Graph<'a> {
+    fn outneighbors(&self, id: NodeId<'a>, edge_type: &'a str) -> &[NodeId<'a>] {
+        self.out
+            .get(&(id, edge_type))
+            .map_or(&[], |neighbors| neighbors.as_slice())
+    }
+
+    fn inneighbors(&self, id: NodeId<'a>, edge_type: &'a str) -> &[NodeId<'a>] {
+        self.into
+            .get(&(id, edge_type))
+            .map_or(&[], |neighbors| neighbors.as_slice())
+    }
+
+    /// The only flow inneighbor, like `inflow_id`.
+    fn inflow_id(&self, id: NodeId<'a>) -> Option<NodeId<'a>> {
+        match self.inneighbors(id, "flow") {
+            [only] => Some(*only),
+            _ => None,
+        }
+    }
+
+    /// The only flow outneighbor, like `outflow_id`.
+    fn outflow_id(&self, id: NodeId<'a>) -> Option<NodeId<'a>> {
+        match self.outneighbors(id, "flow") {
+            [only] => Some(*only),
+            _ => None,
+        }
+    }
+}
+
+/// Check the topology of the network of a model, returning all violations found.
+pub fn check(config: &Config) -> Result<Vec<Violation>, Error> {
+    let database = Database::open(&config.database_path())?;
+    let nodes = database.nodes()?;
+    let edges = database.edges()?;
+
+    let mut violations = Vec::new();
+    let mut report = |check, message| violations.push(Violation { check, message });
+
+    valid_nodes(&nodes, &mut report);
+    valid_edge_types(&edges, &mut report);
+    let graph = create_graph(&nodes, &edges, &mut report);
+    non_positive_subnetwork_id(&nodes, &mut report);
+    incomplete_subnetwork(&graph, &mut report);
+    valid_edges(&graph, &mut report);
+    valid_n_neighbors(&graph, &mut report);
+    valid_pid_connectivity(&database, &graph, &mut report)?;
+    valid_fractional_flow(&database, &graph, &mut report)?;
+
+    Ok(violations)
+}
+
+type Report<'r> = dyn FnMut(&'static str, String) + 'r;
+
+fn valid_nodes(nodes: &[Node], report: &mut Report) {
+    let mut seen = BTreeSet::new();
+    let mut duplicates = BTreeSet::new();
+    for node in nodes {
+        let id = NodeId {
+            node_type: &node.node_type,
+            node_id: node.node_id,
+        };
+        if !seen.insert(id) {
+            duplicates.insert(id);
+        }
+        if !NEIGHBOR_TYPES
+            .iter()
+            .any(|(node_type, _)| *node_type == node.node_type)
+        {
+            report(
+                "valid_nodes",
+                format!(
+                    "Unknown node type {} of node #{}.",
+                    node.node_type, node.node_id
+                ),
+            );
+        }
+    }
+    for id in duplicates {
+        report(
+            "valid_nodes",
+            format!("Multiple occurrences of node {id} found in Node table."),
+        );
+    }
+}
+
+fn valid_edge_types(edges: &[Edge], report: &mut Report) {
+    for edge in edges {
+        if !EDGE_TYPES.contains(&edge.edge_type.as_str()) {
+            report(
+                "valid_edge_types",
+                format!(
+                    "Invalid edge type '{}' for edge #{} from node #{} to node #{}.",
+                    edge.edge_type, edge.edge_id, edge.from_node_id, edge.to_node_id
+                ),
+            );
+        }
+    }
+}
+
+/// Build the graph like `create_graph`, reporting edges to missing nodes and duplicate
+/// edges.
+fn create_graph<'a>(nodes: &'a [Node], edges: &'a [Edge], report: &mut Report) -> Graph<'a> {
+    let mut graph = Graph {
+        nodes: nodes
+            .iter()
+            .map(|node| {
+                let id = NodeId {
+                    node_type: &node.node_type,
+                    node_id: node.node_id,
+                };
+                (id, node)
+            })
+            .collect(),
+        out: HashMap::new(),
+        into: HashMap::new(),
+        edges: Vec::new(),
+    };
+
+    let mut pairs = BTreeSet::new();
+    for edge in edges {
+        let from = NodeId {
+            node_type: &edge.from_node_type,
+            node_id: edge.from_node_id,
+        };
+        let to = NodeId {
+            node_type: &edge.to_node_type,
+            node_id: edge.to_node_id,
+        };
+        let missing: Vec<String> = [from, to]
+            .iter()
+            .filter(|id| !graph.nodes.contains_key(id))
+            .map(ToString::to_string)
+            .collect();
+        if !missing.is_empty() {
+            report(
+                "create_graph",
+                format!(
+                    "Edge #{} from {from} to {to} refers to {}, which is not in the Node table.",
+                    edge.edge_id,
+                    missing.join(" and ")
+                ),
+            );
+            continue;
+        }
+        if !EDGE_TYPES.contains(&edge.edge_type.as_str()) {
+            continue;
+        }
+        if !pairs.insert((from, to)) {
+            report(
+                "create_graph",
+                format!("Duplicate edge #{} from {from} to {to}.", edge.edge_id),
+            );
+            continue;
+        }
+        graph
+            .out
+            .entry((from, &edge.edge_type))
+            .or_default()
+            .push(to);
+        graph
+            .into
+            .entry((to, &edge.edge_type))
+            .or_default()
+            .push(from);
+        graph.edges.push((edge, from, to));
+    }
+    graph
+}
+
+fn non_positive_subnetwork_id(nodes: &[Node], report: &mut Report) {
+    let ids: BTreeSet<i32> = nodes
+        .iter()
+        .filter_map(|node| node.subnetwork_id)
+        .filter(|id| *id <= 0)
+        .collect();
+    for id in ids {
+        report(
+            "non_positive_subnetwork_id",
+            format!("Allocation network id {id} needs to be a positive integer."),
+        );
+    }
+}
+
+/// The number of nodes that are listed, the rest is only counted.
+const MAX_LISTED: usize = 10;
+
+fn incomplete_subnetwork(graph: &Graph, report: &mut Report) {
+    let mut subnetworks: BTreeMap<i32, Vec<NodeId>> = BTreeMap::new();
+    for (id, node) in &graph.nodes {
+        if let Some(subnetwork_id) = node.subnetwork_id {
+            subnetworks.entry(subnetwork_id).or_default().push(*id);
+        }
+    }
+
+    for (subnetwork_id, members) in subnetworks {
+        // Union-find over the edges within the subnetwork, ignoring their direction
+        let index: HashMap<NodeId, usize> =
+            members.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        let mut parent: Vec<usize> = (0..members.len()).collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        for (_, from, to) in &graph.edges {
+            if let (Some(&a), Some(&b)) = (index.get(from), index.get(to)) {
+                let (a, b) = (root(&mut parent, a), root(&mut parent, b));
+                parent[a] = b;
+            }
+        }
+
+        let first = root(&mut parent, 0);
+        let disconnected: Vec<String> = (0..members.len())
+            .filter(|&i| root(&mut parent, i) != first)
+            .map(|i| members[i].to_string())
+            .collect();
+        if !disconnected.is_empty() {
+            let mut listed = disconnected[..disconnected.len().min(MAX_LISTED)].join(", ");
+            if disconnected.len() > MAX_LISTED {
+                listed.push_str(&format!(" and {} more", disconnected.len() - MAX_LISTED));
+            }
+            report(
+                "incomplete_subnetwork",
+                format!(
+                    "All nodes in subnetwork {subnetwork_id} should be connected, but these are not connected to {}: {listed}.",
+                    members[0]
+                ),
+            );
+        }
+    }
+}
+
+fn valid_edges(graph: &Graph, report: &mut Report) {
+    for (edge, from, to) in &graph.edges {
+        let Some((_, allowed)) = NEIGHBOR_TYPES
+            .iter()
+            .find(|(node_type, _)| *node_type == from.node_type)
+        else {
+            continue;
+        };
+        if !allowed.contains(&to.node_type) {
+            report(
+                "valid_edges",
+                format!(
+                    "Cannot connect a {} to a {}, edge #{} from {from} to {to}.",
+                    from.node_type, to.node_type, edge.edge_id
+                ),
+            );
+        }
+    }
+}
+
+fn valid_n_neighbors(graph: &Graph, report: &mut Report) {
+    for id in graph.nodes.keys() {
+        let Some((_, flow, control)) = NEIGHBOR_BOUNDS
+            .iter()
+            .find(|(node_type, _, _)| *node_type == id.node_type)
+        else {
+            continue;
+        };
+        for (bounds, edge_type) in [(flow, "flow"), (control, "control")] {
+            let n_in = graph.inneighbors(*id, edge_type).len();
+            let n_out = graph.outneighbors(*id, edge_type).len();
+            let mut check = |n: usize, min: usize, max: usize, direction: &str| {
+                if n < min {
+                    report(
+                        "valid_n_neighbors",
+                        format!(
+                            "{id} must have at least {min} {edge_type} {direction}(s) (got {n})."
+                        ),
+                    );
+                }
+                if n > max {
+                    report(
+                        "valid_n_neighbors",
+                        format!(
+                            "{id} can have at most {max} {edge_type} {direction}(s) (got {n})."
+                        ),
+                    );
+                }
+            };
+            check(n_in, bounds.in_min, bounds.in_max, "inneighbor");
+            check(n_out, bounds.out_min, bounds.out_max, "outneighbor");
+        }
+    }
+}
+
+fn valid_pid_connectivity(
+    database: &Database,
+    graph: &Graph,
+    report: &mut Report,
+) -> Result<(), Error> {
+    let mut listens: BTreeSet<(i32, String, i32)> = BTreeSet::new();
+    for table in ["PidControl / static", "PidControl / time"] {
+        if !database.has_table(table)? {
+            continue;
+        }
+        let mut statement = database
+            .connection()
+            .prepare(&format!(
+                "SELECT node_id, listen_node_type, listen_node_id FROM \"{table}\""
+            ))
+            .map_err(|source| database.error(source))?;
+        let rows = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|source| database.error(source))?;
+        listens.extend(rows);
+    }
+
+    for (node_id, listen_node_type, listen_node_id) in &listens {
+        let pid_control = NodeId {
+            node_type: "PidControl",
+            node_id: *node_id,
+        };
+        let listen = NodeId {
+            node_type: listen_node_type,
+            node_id: *listen_node_id,
+        };
+        if listen.node_type != "Basin" {
+            report(
+                "valid_pid_connectivity",
+                format!("Listen node {listen} of {pid_control} is not a Basin."),
+            );
+        }
+
+        // Wrong numbers of neighbors are reported by valid_n_neighbors
+        let [controlled] = graph.outneighbors(pid_control, "control") else {
+            continue;
+        };
+        if !matches!(controlled.node_type, "Pump" | "Outlet") {
+            continue;
+        }
+        let sides = [graph.inflow_id(*controlled), graph.outflow_id(*controlled)];
+        if !sides.contains(&Some(listen)) {
+            report(
+                "valid_pid_connectivity",
+                format!("PID listened {listen} is not on either side of controlled {controlled}."),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn valid_fractional_flow(
+    database: &Database,
+    graph: &Graph,
+    report: &mut Report,
+) -> Result<(), Error> {
+    // Fractions per node and control state, the empty state if there is no control
+    let mut fractions: HashMap<(i32, String), f64> = HashMap::new();
+    if database.has_table("FractionalFlow / static")? {
+        let mut statement = database
+            .connection()
+            .prepare("SELECT node_id, fraction, control_state FROM \"FractionalFlow / static\"")
+            .map_err(|source| database.error(source))?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i32>(0)?,
+                    row.get::<_, f64>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|source| database.error(source))?;
+        for (node_id, fraction, control_state) in rows {
+            fractions.insert((node_id, control_state.unwrap_or_default()), fraction);
+        }
+    }
+
+    let fractional_flows: BTreeSet<NodeId> = graph
+        .nodes
+        .keys()
+        .filter(|id| id.node_type == "FractionalFlow")
+        .copied()
+        .collect();
+    let mut control_states: BTreeMap<NodeId, BTreeSet<&str>> = BTreeMap::new();
+    for id in &fractional_flows {
+        let Some(source) = graph.inflow_id(*id) else {
+            continue;
+        };
+        let states = control_states.entry(source).or_default();
+        states.extend(
+            fractions
+                .keys()
+                .filter(|(node_id, _)| *node_id == id.node_id)
+                .map(|(_, state)| state.as_str()),
+        );
+    }
+
+    for (source, states) in control_states {
+        let outflow_ids = graph.outneighbors(source, "flow");
+        if outflow_ids.iter().any(|id| !fractional_flows.contains(id)) {
+            report(
+                "valid_fractional_flow",
+                format!("{source} has outflow to FractionalFlow and other node types."),
+            );
+        }
+        for state in states {
+            let mut sum = 0.0;
+            for id in outflow_ids
+                .iter()
+                .filter(|id| fractional_flows.contains(id))
+            {
+                let Some(&fraction) = fractions.get(&(id.node_id, state.to_string())) else {
+                    continue;
+                };
+                sum += fraction;
+                if fraction < 0.0 {
+                    report(
+                        "valid_fractional_flow",
+                        format!(
+                            "Fractional flow nodes must have non-negative fractions, {id} has {fraction}{}.",
+                            in_state(state)
+                        ),
+                    );
+                }
+            }
+            // Like `isapprox` with its default tolerance
+            if (sum - 1.0f64).abs() > f64::EPSILON.sqrt() * sum.abs().max(1.0) {
+                report(
+                    "valid_fractional_flow",
+                    format!(
+                        "The sum of fractional flow fractions leaving {source} must be ≈1, got {sum}{}.",
+                        in_state(state)
+                    ),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn in_state(control_state: &str) -> String {
+    if control_state.is_empty() {
+        String::new()
+    } else {
+        format!(" in control state {control_state}")
+    }
+}
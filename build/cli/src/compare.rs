@@ -0,0 +1,381 @@
+//! Comparison of the results of two simulations, joined on the keys of each table.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+use arrow::{
+    array::{Array, AsArray, Float64Array},
+    datatypes::{DataType, Float64Type},
+    util::display::{ArrayFormatter, FormatOptions},
+};
+use ribasim::{
+    error::Error,
+    results::{key_columns, row_keys, Table, RESULTS_FILES},
+};
+use serde::Serialize;
+
+use crate::table;
+
+/// The number of rows that are listed by key when they are in only one of the results.
+const MAX_LISTED: usize = 5;
+
+/// Two values are close if `|a - b| <= atol + rtol * |b|`, like `numpy.isclose`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Tolerance {
+    pub atol: f64,
+    pub rtol: f64,
+}
+
+impl Tolerance {
+    fn is_close(&self, a: f64, b: f64) -> bool {
+        a == b || (a.is_nan() && b.is_nan()) || (a - b).abs() <= self.atol + self.rtol * b.abs()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ColumnComparison {
+    pub name: String,
+    /// Absent for columns that are not numeric, which must match exactly.
+    pub max_abs_diff: Option<f64>,
+    pub max_rel_diff: Option<f64>,
+    /// The number of joined rows where the values are not within the tolerance.
+    pub different: usize,
+    /// The key of the row with the largest difference, or the first different row.
+    pub worst_row: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TableComparison {
+    pub name: String,
+    /// Absent if the table is missing in that directory.
+    pub rows_a: Option<usize>,
+    pub rows_b: Option<usize>,
+    pub rows_only_in_a: usize,
+    pub rows_only_in_b: usize,
+    /// The keys of the first few rows that are only in one of the results.
+    pub examples_only_in_a: Vec<String>,
+    pub examples_only_in_b: Vec<String>,
+    pub columns_only_in_a: Vec<String>,
+    pub columns_only_in_b: Vec<String>,
+    pub columns: Vec<ColumnComparison>,
+}
+
+impl TableComparison {
+    pub fn passed(&self) -> bool {
+        self.rows_a.is_some() == self.rows_b.is_some()
+            && self.rows_only_in_a == 0
+            && self.rows_only_in_b == 0
+            && self.columns_only_in_a.is_empty()
+            && self.columns_only_in_b.is_empty()
+            && self.columns.iter().all(|column| column.different == 0)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    pub tolerance: Tolerance,
+    pub tables: Vec<TableComparison>,
+}
+
+impl Comparison {
+    pub fn passed(&self) -> bool {
+        self.tables.iter().all(TableComparison::passed)
+    }
+
+    pub fn print(&self) {
+        for table in &self.tables {
+            let status = if table.passed() { "equal" } else { "DIFFERENT" };
+            println!("{}: {status}", table.name);
+            match (table.rows_a, table.rows_b) {
+                (Some(_), None) => println!("  only in A"),
+                (None, Some(_)) => println!("  only in B"),
+                _ => {}
+            }
+            for (side, count, examples) in [
+                ("A", table.rows_only_in_a, &table.examples_only_in_a),
+                ("B", table.rows_only_in_b, &table.examples_only_in_b),
+            ] {
+                if count > 0 {
+                    println!("  {count} row(s) only in {side}, e.g.:");
+                    for key in examples {
+                        println!("    {key}");
+                    }
+                }
+            }
+            for (side, columns) in [
+                ("A", &table.columns_only_in_a),
+                ("B", &table.columns_only_in_b),
+            ] {
+                if !columns.is_empty() {
+                    println!("  column(s) only in {side}: {}", columns.join(", "));
+                }
+            }
+            if !table.columns.is_empty() {
+                let rows: Vec<Vec<String>> = table
+                    .columns
+                    .iter()
+                    .map(|column| {
+                        vec![
+                            format!("  {}", column.name),
+                            column.max_abs_diff.map_or("-".to_string(), table::number),
+                            column.max_rel_diff.map_or("-".to_string(), table::number),
+                            column.different.to_string(),
+                            column.worst_row.clone().unwrap_or_default(),
+                        ]
+                    })
+                    .collect();
+                table::print(
+                    &[
+                        "  column",
+                        "max_abs_diff",
+                        "max_rel_diff",
+                        "different",
+                        "worst_row",
+                    ],
+                    &rows,
+                );
+            }
+            println!();
+        }
+        let failed = self.tables.iter().filter(|table| !table.passed()).count();
+        if failed == 0 {
+            println!(
+                "All tables are equal within atol={:e} rtol={:e}",
+                self.tolerance.atol, self.tolerance.rtol
+            );
+        } else {
+            println!("{failed} table(s) differ");
+        }
+    }
+}
+
+/// Compare the results tables in two directories, all that exist by default.
+pub fn compare(
+    dir_a: &Path,
+    dir_b: &Path,
+    tables: &[String],
+    tolerance: Tolerance,
+) -> Result<Comparison, Error> {
+    let mut comparisons = Vec::new();
+    for (name, file) in RESULTS_FILES {
+        if !tables.is_empty() && !tables.iter().any(|table| table == name) {
+            continue;
+        }
+        let a = read_optional(&dir_a.join(file))?;
+        let b = read_optional(&dir_b.join(file))?;
+        let comparison = match (&a, &b) {
+            (None, None) if tables.is_empty() => continue,
+            (None, None) => return Err(Error::ResultsNotFound(dir_a.join(file))),
+            (Some(a), Some(b)) => compare_tables(name, a, b, tolerance)?,
+            _ => TableComparison {
+                name: name.to_string(),
+                rows_a: a.as_ref().map(Table::num_rows),
+                rows_b: b.as_ref().map(Table::num_rows),
+                ..Default::default()
+            },
+        };
+        comparisons.push(comparison);
+    }
+    if comparisons.is_empty() {
+        // Nothing to compare is most likely a wrong path, rather than a success
+        return Err(Error::ResultsNotFound(dir_a.join(RESULTS_FILES[0].1)));
+    }
+    Ok(Comparison {
+        tolerance,
+        tables: comparisons,
+    })
+}
+
+fn read_optional(path: &Path) -> Result<Option<Table>, Error> {
+    match Table::read(path) {
+        Ok(table) => Ok(Some(table)),
+        Err(Error::ResultsNotFound(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn compare_tables(
+    name: &str,
+    a: &Table,
+    b: &Table,
+    tolerance: Tolerance,
+) -> Result<TableComparison, Error> {
+    let keys = key_columns(name);
+    let columns = |table: &Table| -> Vec<String> {
+        table
+            .batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .filter(|column| !keys.contains(&column.as_str()))
+            .collect()
+    };
+    let (columns_a, columns_b) = (columns(a), columns(b));
+
+    let rows_a = numbered_row_keys(a, keys)?;
+    let rows_b = numbered_row_keys(b, keys)?;
+    let index_b: HashMap<&str, usize> = rows_b
+        .iter()
+        .enumerate()
+        .map(|(i, key)| (key.as_str(), i))
+        .collect();
+    let index_a: BTreeSet<&str> = rows_a.iter().map(String::as_str).collect();
+    let joined: Vec<(usize, usize)> = rows_a
+        .iter()
+        .enumerate()
+        .filter_map(|(i, key)| index_b.get(key.as_str()).map(|&j| (i, j)))
+        .collect();
+    let only_in_a: Vec<&String> = rows_a
+        .iter()
+        .filter(|key| !index_b.contains_key(key.as_str()))
+        .collect();
+    let only_in_b: Vec<&String> = rows_b
+        .iter()
+        .filter(|key| !index_a.contains(key.as_str()))
+        .collect();
+
+    let mut comparison = TableComparison {
+        name: name.to_string(),
+        rows_a: Some(a.num_rows()),
+        rows_b: Some(b.num_rows()),
+        rows_only_in_a: only_in_a.len(),
+        rows_only_in_b: only_in_b.len(),
+        examples_only_in_a: only_in_a.into_iter().take(MAX_LISTED).cloned().collect(),
+        examples_only_in_b: only_in_b.into_iter().take(MAX_LISTED).cloned().collect(),
+        columns_only_in_a: columns_a
+            .iter()
+            .filter(|column| !columns_b.contains(column))
+            .cloned()
+            .collect(),
+        columns_only_in_b: columns_b
+            .iter()
+            .filter(|column| !columns_a.contains(column))
+            .cloned()
+            .collect(),
+        columns: Vec::new(),
+    };
+
+    for column in columns_a.iter().filter(|column| columns_b.contains(column)) {
+        let column_a = a.batch.column_by_name(column).expect("column exists");
+        let column_b = b.batch.column_by_name(column).expect("column exists");
+        let numeric = column_a.data_type() == &DataType::Float64
+            && column_b.data_type() == &DataType::Float64;
+        let result = if numeric {
+            compare_numbers(
+                column_a.as_primitive::<Float64Type>(),
+                column_b.as_primitive::<Float64Type>(),
+                &joined,
+                &rows_a,
+                tolerance,
+                column,
+            )
+        } else {
+            compare_display(column_a, column_b, &joined, &rows_a, column)
+                .map_err(|source| arrow_error(a, source))?
+        };
+        comparison.columns.push(result);
+    }
+    Ok(comparison)
+}
+
+fn compare_numbers(
+    a: &Float64Array,
+    b: &Float64Array,
+    joined: &[(usize, usize)],
+    keys: &[String],
+    tolerance: Tolerance,
+    name: &str,
+) -> ColumnComparison {
+    let value = |array: &Float64Array, i| {
+        if array.is_null(i) {
+            f64::NAN
+        } else {
+            array.value(i)
+        }
+    };
+    let mut result = ColumnComparison {
+        name: name.to_string(),
+        max_abs_diff: Some(0.0),
+        max_rel_diff: Some(0.0),
+        different: 0,
+        worst_row: None,
+    };
+    let mut worst = 0.0;
+    for &(i, j) in joined {
+        let (x, y) = (value(a, i), value(b, j));
+        let abs_diff = match (x.is_nan(), y.is_nan()) {
+            (true, true) => 0.0,
+            (false, false) => (x - y).abs(),
+            _ => f64::INFINITY,
+        };
+        let rel_diff = if abs_diff == 0.0 {
+            0.0
+        } else {
+            abs_diff / y.abs()
+        };
+        result.max_abs_diff = result.max_abs_diff.map(|max| max.max(abs_diff));
+        result.max_rel_diff = result.max_rel_diff.map(|max| max.max(rel_diff));
+        if !tolerance.is_close(x, y) {
+            result.different += 1;
+            if result.worst_row.is_none() || abs_diff > worst {
+                worst = abs_diff;
+                result.worst_row = Some(keys[i].clone());
+            }
+        }
+    }
+    result
+}
+
+/// Compare values that are not floating point by their textual representation.
+fn compare_display(
+    a: &dyn Array,
+    b: &dyn Array,
+    joined: &[(usize, usize)],
+    keys: &[String],
+    name: &str,
+) -> Result<ColumnComparison, arrow::error::ArrowError> {
+    let options = FormatOptions::default().with_null("null");
+    let format_a = ArrayFormatter::try_new(a, &options)?;
+    let format_b = ArrayFormatter::try_new(b, &options)?;
+    let different: Vec<usize> = joined
+        .iter()
+        .filter(|&&(i, j)| format_a.value(i).to_string() != format_b.value(j).to_string())
+        .map(|&(i, _)| i)
+        .collect();
+    Ok(ColumnComparison {
+        name: name.to_string(),
+        max_abs_diff: None,
+        max_rel_diff: None,
+        different: different.len(),
+        worst_row: different.first().map(|&i| keys[i].clone()),
+    })
+}
+
+/// Describe each row by its key columns, see [`row_keys`].
+///
+/// Rows with the same key are numbered, so that they are joined in order of appearance.
+fn numbered_row_keys(table: &Table, keys: &[&str]) -> Result<Vec<String>, Error> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    Ok(row_keys(table, keys)?
+        .into_iter()
+        .map(|key| {
+            let count = seen.entry(key.clone()).or_default();
+            *count += 1;
+            if *count == 1 {
+                key
+            } else {
+                format!("{key} (duplicate {count})")
+            }
+        })
+        .collect())
+}
+
+fn arrow_error(table: &Table, source: arrow::error::ArrowError) -> Error {
+    Error::Arrow {
+        path: table.path.clone(),
+        source,
+    }
+}
@@ -0,0 +1,633 @@
+//! The model configuration, mirroring `Ribasim.config` in the Julia core.
+//!
+//! The TOML file is read into the same shape as `config.Toml`, so that a model can be
+//! checked without starting Julia. Parsing does not stop at the first problem, all
+//! invalid keys are collected so they can be reported at once.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use arrow::ipc::CompressionType;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use serde::Serialize;
+use thiserror::Error;
+use toml::{Table, Value};
+
+/// Solver algorithms supported by `config.algorithms`.
+pub const ALGORITHMS: [&str; 9] = [
+    "QNDF",
+    "Rosenbrock23",
+    "TRBDF2",
+    "Rodas5",
+    "KenCarp4",
+    "Tsit5",
+    "RK4",
+    "ImplicitEuler",
+    "Euler",
+];
+
+/// Accepted values of `[logging] verbosity`.
+pub const VERBOSITY_LEVELS: [&str; 4] = ["debug", "info", "warn", "error"];
+
+/// Keys that hold a `Float64` in `config.Toml`.
+pub const FLOAT_KEYS: [&str; 7] = [
+    "solver.saveat",
+    "solver.dt",
+    "solver.dtmin",
+    "solver.dtmax",
+    "solver.abstol",
+    "solver.reltol",
+    "allocation.timestep",
+];
+
+/// The TOML section of each node type, with the table kinds that can be read from an
+/// Arrow file instead of the database, as declared in schema.jl.
+pub const NODE_TABLES: [(&str, &[&str]); 15] = [
+    (
+        "basin",
+        &[
+            "static",
+            "time",
+            "profile",
+            "state",
+            "subgrid",
+            "concentration",
+            "concentrationexternal",
+            "concentrationstate",
+        ],
+    ),
+    ("discrete_control", &["variable", "condition", "logic"]),
+    ("flow_boundary", &["static", "time", "concentration"]),
+    ("flow_demand", &["static", "time"]),
+    ("fractional_flow", &["static"]),
+    ("level_boundary", &["static", "time", "concentration"]),
+    ("level_demand", &["static", "time"]),
+    ("linear_resistance", &["static"]),
+    ("manning_resistance", &["static"]),
+    ("outlet", &["static"]),
+    ("pid_control", &["static", "time"]),
+    ("pump", &["static"]),
+    ("tabulated_rating_curve", &["static", "time"]),
+    ("terminal", &["static"]),
+    ("user_demand", &["static", "time"]),
+];
+
+/// The node type of a TOML section, like `PidControl` for `pid_control`.
+pub fn node_type(section: &str) -> String {
+    section
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// The TOML section of a node type, like `pid_control` for `PidControl`.
+pub fn section(node_type: &str) -> String {
+    let mut section = String::new();
+    for (i, char) in node_type.chars().enumerate() {
+        if char.is_uppercase() && i > 0 {
+            section.push('_');
+        }
+        section.extend(char.to_lowercase());
+    }
+    section
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Solver {
+    pub algorithm: String,
+    pub saveat: f64,
+    pub dt: Option<f64>,
+    pub dtmin: f64,
+    pub dtmax: Option<f64>,
+    pub force_dtmin: bool,
+    pub abstol: f64,
+    pub reltol: f64,
+    pub maxiters: i64,
+    pub sparse: bool,
+    pub autodiff: bool,
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Solver {
+            algorithm: "QNDF".to_string(),
+            saveat: 86400.0,
+            dt: None,
+            dtmin: 0.0,
+            dtmax: None,
+            force_dtmin: false,
+            abstol: 1e-6,
+            reltol: 1e-5,
+            maxiters: 1_000_000_000,
+            sparse: true,
+            autodiff: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Results {
+    pub outstate: Option<String>,
+    pub compression: bool,
+    pub compression_level: i64,
+    pub subgrid: bool,
+}
+
+impl Results {
+    /// The compression of the Arrow files that are written like the results.
+    pub fn arrow_compression(&self) -> Option<CompressionType> {
+        self.compression.then_some(CompressionType::ZSTD)
+    }
+}
+
+impl Default for Results {
+    fn default() -> Self {
+        Results {
+            outstate: None,
+            compression: true,
+            compression_level: 6,
+            subgrid: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Logging {
+    pub verbosity: String,
+    pub timing: bool,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging {
+            verbosity: "info".to_string(),
+            timing: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Allocation {
+    pub timestep: f64,
+    pub use_allocation: bool,
+}
+
+impl Default for Allocation {
+    fn default() -> Self {
+        Allocation {
+            timestep: 86400.0,
+            use_allocation: false,
+        }
+    }
+}
+
+/// The contents of the TOML file, equivalent to `config.Toml`.
+#[derive(Debug, Clone)]
+pub struct Toml {
+    pub starttime: NaiveDateTime,
+    pub endtime: NaiveDateTime,
+    pub crs: String,
+    pub ribasim_version: String,
+    pub input_dir: String,
+    pub results_dir: String,
+    pub database: String,
+    pub allocation: Allocation,
+    pub solver: Solver,
+    pub logging: Logging,
+    pub results: Results,
+    /// Arrow files per node section and table kind, e.g. `basin` -> `time` -> `basin/time.arrow`.
+    pub tables: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// A parsed TOML file together with the directory it was read from.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub toml: Toml,
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    /// The TOML file could not be read.
+    #[error("Failed to read {}: {1}", .0.display())]
+    Io(PathBuf, #[source] io::Error),
+    /// The file is not valid TOML.
+    #[error("Failed to parse {}: {1}", .0.display())]
+    Syntax(PathBuf, #[source] toml::de::Error),
+    /// The TOML is valid, but does not match the configuration schema.
+    #[error("Invalid configuration in {}:{}", .0.display(), list_errors(.1))]
+    Invalid(PathBuf, Vec<String>),
+}
+
+fn list_errors(errors: &[String]) -> String {
+    errors.iter().map(|error| format!("\n  {error}")).collect()
+}
+
+impl Config {
+    /// Read and parse a TOML file.
+    pub fn from_path(path: &Path) -> Result<Config, ConfigError> {
+        Config::from_table(path, read_table(path)?)
+    }
+
+    /// Parse a TOML table that was read from `path`.
+    pub fn from_table(path: &Path, table: Table) -> Result<Config, ConfigError> {
+        let toml = Toml::from_table(table)
+            .map_err(|errors| ConfigError::Invalid(path.to_owned(), errors))?;
+        let dir = path.parent().unwrap_or(Path::new("")).to_owned();
+        Ok(Config { toml, dir })
+    }
+
+    /// Construct a path relative to both the TOML directory and the `input_dir`.
+    pub fn input_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.dir.join(&self.input_dir).join(path)
+    }
+
+    /// Construct a path relative to both the TOML directory and the `results_dir`.
+    pub fn results_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.dir.join(&self.results_dir).join(path)
+    }
+
+    /// The path to the GeoPackage database.
+    pub fn database_path(&self) -> PathBuf {
+        self.input_path(&self.database)
+    }
+}
+
+/// Convert the seconds passed since the simulation start to the nearest date-time, like
+/// `datetime_since` does in the Julia core.
+pub fn datetime_since(t: f64, t0: NaiveDateTime) -> NaiveDateTime {
+    t0 + TimeDelta::milliseconds((1000.0 * t).round() as i64)
+}
+
+/// Parse a date, or a date-time with a space or `T` separator.
+pub fn parse_datetime(text: &str) -> Option<NaiveDateTime> {
+    [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    })
+}
+
+/// Read a TOML file without checking it against the configuration schema.
+pub fn read_table(path: &Path) -> Result<Table, ConfigError> {
+    let text = fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_owned(), err))?;
+    text.parse()
+        .map_err(|err| ConfigError::Syntax(path.to_owned(), err))
+}
+
+impl Deref for Config {
+    type Target = Toml;
+
+    fn deref(&self) -> &Toml {
+        &self.toml
+    }
+}
+
+impl Toml {
+    /// Convert a parsed TOML table, collecting all errors that are found.
+    pub fn from_table(table: Table) -> Result<Toml, Vec<String>> {
+        let mut root = Section::new("", table);
+
+        let starttime = root.required::<NaiveDateTime>("starttime");
+        let endtime = root.required::<NaiveDateTime>("endtime");
+        let crs = root.required::<String>("crs");
+        let ribasim_version = root.required::<String>("ribasim_version");
+        let input_dir = root.required::<String>("input_dir");
+        let results_dir = root.required::<String>("results_dir");
+        let database = root.get("database", "database.gpkg".to_string());
+
+        let allocation = root.section("allocation", Allocation::parse);
+        let solver = root.section("solver", Solver::parse);
+        let logging = root.section("logging", Logging::parse);
+        let results = root.section("results", Results::parse);
+
+        let mut tables = BTreeMap::new();
+        for (node, kinds) in NODE_TABLES {
+            let paths = root.section(node, |section| {
+                let mut paths = BTreeMap::new();
+                for kind in kinds.iter() {
+                    if let Some(path) = section.optional::<String>(kind) {
+                        paths.insert(kind.to_string(), path);
+                    }
+                }
+                paths
+            });
+            if !paths.is_empty() {
+                tables.insert(node.to_string(), paths);
+            }
+        }
+
+        // Same rule as `valid_config`
+        if let (Some(starttime), Some(endtime)) = (starttime, endtime) {
+            if starttime >= endtime {
+                root.error(
+                    "endtime",
+                    "The model starttime must be before the endtime.".to_string(),
+                );
+            }
+        }
+
+        let errors = root.finish();
+        match (
+            starttime,
+            endtime,
+            crs,
+            ribasim_version,
+            input_dir,
+            results_dir,
+        ) {
+            (
+                Some(starttime),
+                Some(endtime),
+                Some(crs),
+                Some(ribasim_version),
+                Some(input_dir),
+                Some(results_dir),
+            ) if errors.is_empty() => Ok(Toml {
+                starttime,
+                endtime,
+                crs,
+                ribasim_version,
+                input_dir,
+                results_dir,
+                database,
+                allocation,
+                solver,
+                logging,
+                results,
+                tables,
+            }),
+            _ => Err(errors),
+        }
+    }
+}
+
+impl Solver {
+    fn parse(section: &mut Section) -> Solver {
+        let default = Solver::default();
+        let solver = Solver {
+            algorithm: section.get("algorithm", default.algorithm),
+            saveat: section.get("saveat", default.saveat),
+            dt: section.optional("dt"),
+            dtmin: section.get("dtmin", default.dtmin),
+            dtmax: section.optional("dtmax"),
+            force_dtmin: section.get("force_dtmin", default.force_dtmin),
+            abstol: section.get("abstol", default.abstol),
+            reltol: section.get("reltol", default.reltol),
+            maxiters: section.get("maxiters", default.maxiters),
+            sparse: section.get("sparse", default.sparse),
+            autodiff: section.get("autodiff", default.autodiff),
+        };
+
+        if !ALGORITHMS.contains(&solver.algorithm.as_str()) {
+            section.error(
+                "algorithm",
+                format!(
+                    "Given solver algorithm {} not supported, available options are: ({}).",
+                    solver.algorithm,
+                    ALGORITHMS.join(", ")
+                ),
+            );
+        }
+
+        // Same rules as `convert_saveat`
+        let saveat = solver.saveat;
+        if saveat.is_finite() && saveat != saveat.round() {
+            section.error(
+                "saveat",
+                format!("A finite saveat must be an integer number of seconds, got {saveat}."),
+            );
+        } else if saveat.is_nan() || saveat == f64::NEG_INFINITY {
+            section.error("saveat", format!("Invalid saveat {saveat}."));
+        }
+
+        // Same rules as `convert_dt`
+        if let Some(dt) = solver.dt {
+            if !(0.0 < dt && dt < f64::INFINITY) {
+                section.error(
+                    "dt",
+                    format!(
+                        "Invalid dt {dt}, it must be positive and finite, or left out for adaptive time stepping."
+                    ),
+                );
+            }
+        }
+
+        solver
+    }
+}
+
+impl Results {
+    fn parse(section: &mut Section) -> Results {
+        let default = Results::default();
+        Results {
+            outstate: section.optional("outstate"),
+            compression: section.get("compression", default.compression),
+            compression_level: section.get("compression_level", default.compression_level),
+            subgrid: section.get("subgrid", default.subgrid),
+        }
+    }
+}
+
+impl Logging {
+    fn parse(section: &mut Section) -> Logging {
+        let default = Logging::default();
+        let logging = Logging {
+            verbosity: section.get("verbosity", default.verbosity),
+            timing: section.get("timing", default.timing),
+        };
+        if !VERBOSITY_LEVELS.contains(&logging.verbosity.as_str()) {
+            section.error(
+                "verbosity",
+                format!(
+                    "verbosity {} not supported, choose one of: {}.",
+                    logging.verbosity,
+                    VERBOSITY_LEVELS.join(" ")
+                ),
+            );
+        }
+        logging
+    }
+}
+
+impl Allocation {
+    fn parse(section: &mut Section) -> Allocation {
+        let default = Allocation::default();
+        Allocation {
+            timestep: section.get("timestep", default.timestep),
+            use_allocation: section.get("use_allocation", default.use_allocation),
+        }
+    }
+}
+
+/// Conversion of a TOML value to the type of a configuration key.
+trait FromValue: Sized {
+    /// Description of the expected type, used in error messages.
+    const EXPECTED: &'static str;
+
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+impl FromValue for f64 {
+    const EXPECTED: &'static str = "a number";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Float(x) => Some(*x),
+            Value::Integer(x) => Some(*x as f64),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for i64 {
+    const EXPECTED: &'static str = "an integer";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Integer(x) => Some(*x),
+            // Like Julia, accept floats that are exact integers, e.g. `maxiters = 1e9`
+            Value::Float(x) if x.fract() == 0.0 && x.abs() < i64::MAX as f64 => Some(*x as i64),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for bool {
+    const EXPECTED: &'static str = "a boolean";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_bool()
+    }
+}
+
+impl FromValue for String {
+    const EXPECTED: &'static str = "a string";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_str().map(str::to_string)
+    }
+}
+
+impl FromValue for NaiveDateTime {
+    const EXPECTED: &'static str = "a date or date-time without offset";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        let datetime = value.as_datetime()?;
+        if datetime.offset.is_some() {
+            return None;
+        }
+        let date = datetime.date?;
+        let date = NaiveDate::from_ymd_opt(date.year.into(), date.month.into(), date.day.into())?;
+        let time = match datetime.time {
+            Some(time) => NaiveTime::from_hms_nano_opt(
+                time.hour.into(),
+                time.minute.into(),
+                time.second.into(),
+                time.nanosecond,
+            )?,
+            None => NaiveTime::MIN,
+        };
+        Some(date.and_time(time))
+    }
+}
+
+/// A TOML table from which the known keys are taken one by one.
+///
+/// Errors are collected rather than returned, and any keys that are left when the
+/// section is finished are reported as unknown.
+struct Section {
+    name: String,
+    table: Table,
+    errors: Vec<String>,
+}
+
+impl Section {
+    fn new(name: &str, table: Table) -> Section {
+        Section {
+            name: name.to_string(),
+            table,
+            errors: Vec::new(),
+        }
+    }
+
+    /// The full name of a key, e.g. `solver.dt`.
+    fn key(&self, key: &str) -> String {
+        if self.name.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{key}", self.name)
+        }
+    }
+
+    fn error(&mut self, key: &str, message: String) {
+        let key = self.key(key);
+        self.errors.push(format!("{key}: {message}"));
+    }
+
+    fn optional<T: FromValue>(&mut self, key: &str) -> Option<T> {
+        let value = self.table.remove(key)?;
+        let converted = T::from_value(&value);
+        if converted.is_none() {
+            self.error(
+                key,
+                format!("expected {}, got {} {value}", T::EXPECTED, value.type_str()),
+            );
+        }
+        converted
+    }
+
+    fn required<T: FromValue>(&mut self, key: &str) -> Option<T> {
+        if !self.table.contains_key(key) {
+            self.error(key, "missing required key".to_string());
+        }
+        self.optional(key)
+    }
+
+    fn get<T: FromValue>(&mut self, key: &str, default: T) -> T {
+        self.optional(key).unwrap_or(default)
+    }
+
+    /// Parse a subsection with `parse`, or from an empty table if it is absent.
+    fn section<T>(&mut self, key: &str, parse: impl FnOnce(&mut Section) -> T) -> T {
+        let table = match self.table.remove(key) {
+            Some(Value::Table(table)) => table,
+            Some(value) => {
+                self.error(key, format!("expected a table, got {}", value.type_str()));
+                Table::new()
+            }
+            None => Table::new(),
+        };
+        let mut section = Section::new(&self.key(key), table);
+        let parsed = parse(&mut section);
+        self.errors.extend(section.finish());
+        parsed
+    }
+
+    /// Return all errors, including those for unknown keys.
+    fn finish(mut self) -> Vec<String> {
+        let unknown: Vec<String> = self.table.keys().cloned().collect();
+        for key in unknown {
+            self.error(&key, "unknown key".to_string());
+        }
+        self.errors
+    }
+}
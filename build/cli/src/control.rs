@@ -0,0 +1,232 @@
+//! Timeline of the DiscreteControl states in the control results.
+
+use std::collections::BTreeMap;
+
+use chrono::{NaiveDateTime, TimeDelta};
+use ribasim::{config::Config, error::Error, results::Table};
+use serde::Serialize;
+
+use crate::table;
+
+/// A period in which a control node stays in the same control state.
+#[derive(Debug, Serialize)]
+pub struct StateSpell {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    /// The truth state at the start of the period.
+    pub truth_state: String,
+    pub control_state: String,
+    /// The duration in seconds.
+    pub duration: f64,
+}
+
+/// The time window with the most switches of a control node.
+#[derive(Debug, Serialize)]
+pub struct Chattering {
+    pub start: NaiveDateTime,
+    pub switches: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ControlNode {
+    pub control_node_id: i32,
+    /// The number of changes of the control state.
+    pub switches: usize,
+    /// The total duration in seconds per control state.
+    pub durations: BTreeMap<String, f64>,
+    /// The busiest window, if it has more than the allowed number of switches.
+    pub chattering: Option<Chattering>,
+    pub timeline: Vec<StateSpell>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ControlReport {
+    /// The length of the chattering window in seconds.
+    pub window: f64,
+    pub max_switches: usize,
+    pub nodes: Vec<ControlNode>,
+}
+
+impl ControlReport {
+    /// Read control.arrow and follow the control state of the given nodes, or all nodes.
+    ///
+    /// A node chatters if it switches more than `max_switches` times within `window`
+    /// seconds.
+    pub fn from_results(
+        config: &Config,
+        nodes: &[i32],
+        window: f64,
+        max_switches: usize,
+    ) -> Result<ControlReport, Error> {
+        let control = Table::read_results(config, "control")?;
+        let time = control.times("time")?;
+        let control_node_id = control.i32s("control_node_id")?;
+        let truth_state = control.strings("truth_state")?;
+        let control_state = control.strings("control_state")?;
+
+        let mut changes: BTreeMap<i32, Vec<(NaiveDateTime, String, String)>> = BTreeMap::new();
+        for i in 0..control.num_rows() {
+            let Some(node_id) = control_node_id[i] else {
+                continue;
+            };
+            if !nodes.is_empty() && !nodes.contains(&node_id) {
+                continue;
+            }
+            changes.entry(node_id).or_default().push((
+                time[i],
+                truth_state[i].clone().unwrap_or_default(),
+                control_state[i].clone().unwrap_or_default(),
+            ));
+        }
+        if let Some(missing) = nodes.iter().find(|node| !changes.contains_key(node)) {
+            return Err(Error::InvalidArgument(format!(
+                "DiscreteControl #{missing} is not in the control results"
+            )));
+        }
+
+        let window_length = TimeDelta::milliseconds((window * 1000.0).round() as i64);
+        let nodes = changes
+            .into_iter()
+            .map(|(control_node_id, mut changes)| {
+                changes.sort_by_key(|(time, _, _)| *time);
+                let mut timeline: Vec<StateSpell> = Vec::new();
+                let mut switch_times = Vec::new();
+                for (i, (start, truth_state, control_state)) in changes.iter().enumerate() {
+                    let end = changes
+                        .get(i + 1)
+                        .map_or(config.endtime, |(time, _, _)| *time);
+                    match timeline.last_mut() {
+                        Some(spell) if spell.control_state == *control_state => {
+                            spell.end = end;
+                        }
+                        last => {
+                            if last.is_some() {
+                                switch_times.push(*start);
+                            }
+                            timeline.push(StateSpell {
+                                start: *start,
+                                end,
+                                truth_state: truth_state.clone(),
+                                control_state: control_state.clone(),
+                                duration: 0.0,
+                            });
+                        }
+                    }
+                }
+
+                let mut durations: BTreeMap<String, f64> = BTreeMap::new();
+                for spell in &mut timeline {
+                    spell.duration = (spell.end - spell.start).num_milliseconds() as f64 / 1000.0;
+                    *durations.entry(spell.control_state.clone()).or_default() += spell.duration;
+                }
+
+                ControlNode {
+                    control_node_id,
+                    switches: switch_times.len(),
+                    durations,
+                    chattering: busiest_window(&switch_times, window_length)
+                        .filter(|window| window.switches > max_switches),
+                    timeline,
+                }
+            })
+            .collect();
+
+        Ok(ControlReport {
+            window,
+            max_switches,
+            nodes,
+        })
+    }
+
+    /// Print a line per control node, and the timelines if asked for.
+    pub fn print(&self, timeline: bool) {
+        let rows: Vec<Vec<String>> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let durations: Vec<String> = node
+                    .durations
+                    .iter()
+                    .map(|(state, duration)| format!("{state} {}", table::duration(*duration)))
+                    .collect();
+                vec![
+                    node.control_node_id.to_string(),
+                    node.switches.to_string(),
+                    durations.join(", "),
+                    node.chattering.as_ref().map_or_else(String::new, |window| {
+                        format!("{} switches from {}", window.switches, window.start)
+                    }),
+                ]
+            })
+            .collect();
+        table::print(
+            &[
+                "control_node_id",
+                "switches",
+                "time_per_state",
+                "chattering",
+            ],
+            &rows,
+        );
+
+        let chattering = self
+            .nodes
+            .iter()
+            .filter(|node| node.chattering.is_some())
+            .count();
+        if chattering > 0 {
+            println!();
+            println!(
+                "{chattering} node(s) switch more than {} times within {}, check the DiscreteControl / condition table",
+                self.max_switches,
+                table::duration(self.window)
+            );
+        }
+
+        if timeline {
+            for node in &self.nodes {
+                println!();
+                println!("DiscreteControl #{}", node.control_node_id);
+                let rows: Vec<Vec<String>> = node
+                    .timeline
+                    .iter()
+                    .map(|spell| {
+                        vec![
+                            spell.start.to_string(),
+                            spell.end.to_string(),
+                            spell.control_state.clone(),
+                            spell.truth_state.clone(),
+                            table::duration(spell.duration),
+                        ]
+                    })
+                    .collect();
+                table::print(
+                    &["start", "end", "control_state", "truth_state", "duration"],
+                    &rows,
+                );
+            }
+        }
+    }
+}
+
+/// The window of the given length that starts at a switch and holds the most switches.
+fn busiest_window(switch_times: &[NaiveDateTime], length: TimeDelta) -> Option<Chattering> {
+    let mut busiest: Option<Chattering> = None;
+    let mut end = 0;
+    for (start, time) in switch_times.iter().enumerate() {
+        while end < switch_times.len() && switch_times[end] < *time + length {
+            end += 1;
+        }
+        let switches = end - start;
+        if busiest
+            .as_ref()
+            .is_none_or(|busiest| switches > busiest.switches)
+        {
+            busiest = Some(Chattering {
+                start: *time,
+                switches,
+            });
+        }
+    }
+    busiest
+}
@@ -0,0 +1,304 @@
+//! Coverage of the simulation period by the time tables of the input.
+//!
+//! The Julia core ignores rows outside the simulation period, and holds the first or
+//! last value, or extrapolates, where a time series does not cover it. Unsorted rows are
+//! rejected in Arrow files, and are likely a mistake in the database.
+
+use std::collections::BTreeMap;
+
+use arrow::{
+    array::AsArray,
+    compute::cast,
+    datatypes::{DataType, Int64Type},
+};
+use chrono::NaiveDateTime;
+use ribasim::{
+    config::{parse_datetime, Config},
+    error::Error,
+    geopackage::{quote, Database},
+    results::Table,
+    schema::{Schema, SCHEMAS},
+};
+use serde::Serialize;
+
+use crate::table;
+
+/// A period between two timestamps of a time series without rows in between.
+#[derive(Debug, Serialize)]
+pub struct Gap {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    /// The part of the gap within the simulation period, in seconds.
+    pub duration: f64,
+}
+
+/// The time series of one node, and for demands one priority, in a time table.
+#[derive(Debug, Serialize)]
+pub struct Series {
+    /// The database table name, like `Basin / time`.
+    pub table: String,
+    pub node_id: i32,
+    pub priority: Option<i32>,
+    pub rows: usize,
+    pub first: NaiveDateTime,
+    pub last: NaiveDateTime,
+    /// The longest time between timestamps within the simulation period, in seconds.
+    pub largest_gap: f64,
+    /// The gaps longer than the allowed maximum.
+    pub gaps: Vec<Gap>,
+    /// The number of rows with an earlier time than the row before it.
+    pub unsorted_rows: usize,
+    pub starts_late: bool,
+    pub ends_early: bool,
+}
+
+impl Series {
+    pub fn covers(&self) -> bool {
+        !self.starts_late && !self.ends_early && self.gaps.is_empty() && self.unsorted_rows == 0
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CoverageReport {
+    pub starttime: NaiveDateTime,
+    pub endtime: NaiveDateTime,
+    pub max_gap: Option<f64>,
+    pub series: Vec<Series>,
+}
+
+/// The rows of a time table: node ID, priority and time.
+type Rows = Vec<(i32, Option<i32>, NaiveDateTime)>;
+
+impl CoverageReport {
+    /// Read the time tables from the database or their Arrow files, and check every
+    /// time series against the simulation period. Gaps are only reported if `max_gap`
+    /// seconds is given.
+    pub fn from_config(config: &Config, max_gap: Option<f64>) -> Result<CoverageReport, Error> {
+        let database = Database::open(&config.database_path())?;
+        let mut series = Vec::new();
+        for schema in SCHEMAS.iter().filter(|schema| schema.kind == "time") {
+            let path = config
+                .tables
+                .get(schema.section)
+                .and_then(|paths| paths.get(schema.kind));
+            let rows = match path {
+                Some(path) => read_arrow(schema, &Table::read(&config.input_path(path))?)?,
+                None if database.has_table(&schema.table_name())? => {
+                    read_database(schema, &database)?
+                }
+                None => continue,
+            };
+            series.extend(check(config, schema, rows, max_gap));
+        }
+        Ok(CoverageReport {
+            starttime: config.starttime,
+            endtime: config.endtime,
+            max_gap,
+            series,
+        })
+    }
+
+    /// The series that do not cover the simulation period.
+    pub fn failures(&self) -> impl Iterator<Item = &Series> {
+        self.series.iter().filter(|series| !series.covers())
+    }
+
+    /// Print the series that do not cover the simulation period, or all if asked for.
+    pub fn print(&self, all: bool) {
+        let rows: Vec<Vec<String>> = self
+            .series
+            .iter()
+            .filter(|series| all || !series.covers())
+            .map(|series| {
+                vec![
+                    series.table.clone(),
+                    series.node_id.to_string(),
+                    series
+                        .priority
+                        .map_or_else(String::new, |priority| priority.to_string()),
+                    series.rows.to_string(),
+                    series.first.to_string(),
+                    series.last.to_string(),
+                    table::duration(series.largest_gap),
+                    self.problems(series).join(", "),
+                ]
+            })
+            .collect();
+        if !rows.is_empty() {
+            table::print(
+                &[
+                    "table",
+                    "node_id",
+                    "priority",
+                    "rows",
+                    "first",
+                    "last",
+                    "largest_gap",
+                    "problems",
+                ],
+                &rows,
+            );
+            println!();
+        }
+
+        let failures = self.failures().count();
+        if failures == 0 {
+            println!(
+                "All {} time series cover {} to {}",
+                self.series.len(),
+                self.starttime,
+                self.endtime
+            );
+        } else {
+            println!(
+                "{failures} of {} time series do not cover {} to {}",
+                self.series.len(),
+                self.starttime,
+                self.endtime
+            );
+        }
+    }
+
+    fn problems(&self, series: &Series) -> Vec<String> {
+        let mut problems = Vec::new();
+        if series.starts_late {
+            let late = (series.first - self.starttime).num_milliseconds() as f64 / 1000.0;
+            problems.push(format!("starts {} late", table::duration(late)));
+        }
+        if series.ends_early {
+            let early = (self.endtime - series.last).num_milliseconds() as f64 / 1000.0;
+            problems.push(format!("ends {} early", table::duration(early)));
+        }
+        for gap in &series.gaps {
+            problems.push(format!(
+                "gap of {} from {}",
+                table::duration(gap.duration),
+                gap.start
+            ));
+        }
+        if series.unsorted_rows > 0 {
+            problems.push(format!("{} unsorted row(s)", series.unsorted_rows));
+        }
+        problems
+    }
+}
+
+/// Group the rows per node and priority, and check each group.
+fn check(config: &Config, schema: &Schema, rows: Rows, max_gap: Option<f64>) -> Vec<Series> {
+    let mut groups: BTreeMap<(i32, Option<i32>), Vec<NaiveDateTime>> = BTreeMap::new();
+    for (node_id, priority, time) in rows {
+        groups.entry((node_id, priority)).or_default().push(time);
+    }
+
+    groups
+        .into_iter()
+        .map(|((node_id, priority), times)| {
+            let unsorted_rows = times.windows(2).filter(|pair| pair[1] < pair[0]).count();
+            let mut unique = times.clone();
+            unique.sort_unstable();
+            unique.dedup();
+
+            let mut largest_gap: f64 = 0.0;
+            let mut gaps = Vec::new();
+            for pair in unique.windows(2) {
+                // Only the part within the simulation period matters
+                let start = pair[0].max(config.starttime);
+                let end = pair[1].min(config.endtime);
+                if end <= start {
+                    continue;
+                }
+                let duration = (end - start).num_milliseconds() as f64 / 1000.0;
+                largest_gap = largest_gap.max(duration);
+                if max_gap.is_some_and(|max_gap| duration > max_gap) {
+                    gaps.push(Gap {
+                        start: pair[0],
+                        end: pair[1],
+                        duration,
+                    });
+                }
+            }
+
+            let first = unique[0];
+            let last = unique[unique.len() - 1];
+            Series {
+                table: schema.table_name(),
+                node_id,
+                priority,
+                rows: times.len(),
+                first,
+                last,
+                largest_gap,
+                gaps,
+                unsorted_rows,
+                starts_late: first > config.starttime,
+                ends_early: last < config.endtime,
+            }
+        })
+        .collect()
+}
+
+fn read_database(schema: &Schema, database: &Database) -> Result<Rows, Error> {
+    let name = schema.table_name();
+    let priority = if schema.column("priority").is_some() {
+        "priority"
+    } else {
+        "NULL"
+    };
+    let sql = format!(
+        "SELECT node_id, {priority}, time FROM {} ORDER BY rowid",
+        quote(&name)
+    );
+    let connection = database.connection();
+    let mut statement = connection
+        .prepare(&sql)
+        .map_err(|source| database.error(source))?;
+    let rows = statement
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i32>(0)?,
+                row.get::<_, Option<i32>>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })
+        .map_err(|source| database.error(source))?;
+    rows.map(|row| {
+        let (node_id, priority, time) = row.map_err(|source| database.error(source))?;
+        let time = parse_datetime(&time).ok_or_else(|| Error::InvalidTable {
+            table: name.clone(),
+            message: format!("invalid time {time:?}"),
+        })?;
+        Ok((node_id, priority, time))
+    })
+    .collect()
+}
+
+fn read_arrow(schema: &Schema, table: &Table) -> Result<Rows, Error> {
+    let integers = |column: &str| -> Result<Vec<Option<i64>>, Error> {
+        let invalid = |message: String| Error::InvalidTable {
+            table: schema.table_name(),
+            message: format!("{message} in {}", table.path.display()),
+        };
+        let array = table
+            .batch
+            .column_by_name(column)
+            .ok_or_else(|| invalid(format!("column {column} is missing")))?;
+        // node_id is Int32, but Int64 in FlowDemand
+        let array = cast(array, &DataType::Int64)
+            .map_err(|_| invalid(format!("column {column} is not an integer")))?;
+        Ok(array.as_primitive::<Int64Type>().iter().collect())
+    };
+    let node_id = integers("node_id")?;
+    let priority = if schema.column("priority").is_some() {
+        integers("priority")?
+    } else {
+        vec![None; table.num_rows()]
+    };
+    let time = table.times("time")?;
+    Ok((0..table.num_rows())
+        .filter_map(|i| {
+            let node_id = i32::try_from(node_id[i]?).ok()?;
+            let priority = priority[i].and_then(|priority| i32::try_from(priority).ok());
+            Some((node_id, priority, time[i]))
+        })
+        .collect())
+}
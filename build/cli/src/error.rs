@@ -0,0 +1,214 @@
+//! Errors of the CLI, and the exit code each class of error results in.
+
+use std::{fmt, path::PathBuf, process::ExitCode};
+
+use chrono::NaiveDateTime;
+use thiserror::Error;
+
+use crate::{config::ConfigError, library::LIB_NAME};
+
+/// Exit codes of the `ribasim` executable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+    Success = 0,
+    /// The simulation failed, the Julia core rejected the model, or writing output failed.
+    ModelFailed = 1,
+    /// Invalid command line arguments, this is also what clap exits with.
+    Usage = 2,
+    /// The model is invalid, as detected before starting Julia.
+    InvalidModel = 3,
+    /// libribasim could not be found or loaded, or lacks a required symbol.
+    BrokenInstall = 4,
+    /// Julia could not be initialized.
+    JuliaInit = 5,
+    /// The results are missing or cannot be read.
+    InvalidResults = 6,
+    /// The simulation succeeded, but its water balance error exceeds the given limit.
+    BalanceError = 7,
+    /// The compared results differ beyond the tolerances.
+    ResultsDiffer = 8,
+    /// The simulation was stopped by SIGINT or SIGTERM, results were written up to then.
+    Interrupted = 130,
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Status::Success => "success",
+            Status::ModelFailed => "failed",
+            Status::Usage => "invalid arguments",
+            Status::InvalidModel => "invalid model",
+            Status::BrokenInstall => "broken installation",
+            Status::JuliaInit => "Julia initialization failed",
+            Status::InvalidResults => "invalid results",
+            Status::BalanceError => "balance error",
+            Status::ResultsDiffer => "results differ",
+            Status::Interrupted => "interrupted",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Exit code documentation for the `--help` output.
+pub const EXIT_CODES_HELP: &str = "\
+Exit codes:
+    0  success
+    1  the simulation failed, the Julia core rejected the model, or writing output failed
+    2  invalid command line arguments
+    3  the model is invalid, as detected before starting Julia
+    4  broken installation: libribasim not found, not loadable or incomplete
+    5  Julia could not be initialized
+    6  the results are missing or cannot be read
+    7  the water balance error exceeds --max-relative-error or --max-balance-error
+    8  the compared results differ beyond the tolerances
+  130  the simulation was interrupted, results were written up to that moment";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[error("TOML file not found: {}", .0.display())]
+    TomlNotFound(PathBuf),
+
+    #[error(
+        "Path cannot be passed to libribasim, it is not valid UTF-8 or contains a NUL byte: {0:?}"
+    )]
+    InvalidPathEncoding(PathBuf),
+
+    #[error("Failed to write the resolved TOML file: {0}")]
+    ResolvedToml(#[source] std::io::Error),
+
+    #[error(
+        "Could not find {LIB_NAME}, use --lib or RIBASIM_LIB to point to it. Tried:{}",
+        list_paths(.tried)
+    )]
+    LibraryNotFound { tried: Vec<PathBuf> },
+
+    #[error("Failed to load {}: {source}", .path.display())]
+    LibraryLoad {
+        path: PathBuf,
+        #[source]
+        source: libloading::Error,
+    },
+
+    #[error("Symbol `{symbol}` not found in {}, the library may be from an incompatible version: {source}", .path.display())]
+    MissingSymbol {
+        symbol: &'static str,
+        path: PathBuf,
+        #[source]
+        source: libloading::Error,
+    },
+
+    #[error("Failed to initialize Julia")]
+    JuliaInit,
+
+    #[error("Ribasim exited with code {0}")]
+    ModelFailed(i32),
+
+    #[error("BMI function `{function}` failed: {message}")]
+    Bmi {
+        function: &'static str,
+        message: String,
+    },
+
+    #[error("Another model is already initialized, libribasim supports only one at a time")]
+    ModelActive,
+
+    #[error("Variable name cannot be passed to libribasim: {0:?}")]
+    InvalidVarName(String),
+
+    #[error("Variable {name} has unsupported type {var_type}")]
+    UnsupportedVarType { name: String, var_type: String },
+
+    #[error("Failed to read {}: {source}", .path.display())]
+    Database {
+        path: PathBuf,
+        #[source]
+        source: rusqlite::Error,
+    },
+
+    #[error("Table {table}: {message}")]
+    InvalidTable { table: String, message: String },
+
+    #[error("{0}")]
+    Repl(String),
+
+    #[error("{0}")]
+    InvalidArgument(String),
+
+    #[error("Results file not found: {}, run the model first", .0.display())]
+    ResultsNotFound(PathBuf),
+
+    #[error("Failed to read {}: {source}", .path.display())]
+    Arrow {
+        path: PathBuf,
+        #[source]
+        source: arrow::error::ArrowError,
+    },
+
+    #[error("{}: {message}", .path.display())]
+    InvalidResults { path: PathBuf, message: String },
+
+    #[error("{}: {message}", .path.display())]
+    InvalidArchive { path: PathBuf, message: String },
+
+    #[error("Failed to write {}: {source}", .path.display())]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("Failed to install the signal handler: {0}")]
+    SignalHandler(String),
+
+    #[error("The model stopped advancing at model time {0}, see the log for the reason")]
+    Stalled(NaiveDateTime),
+}
+
+impl Error {
+    /// The class of the error, which determines the exit code.
+    pub fn status(&self) -> Status {
+        match self {
+            Error::Config(_)
+            | Error::TomlNotFound(_)
+            | Error::Database { .. }
+            | Error::InvalidTable { .. }
+            | Error::InvalidArchive { .. } => Status::InvalidModel,
+            Error::InvalidPathEncoding(_) => Status::Usage,
+            Error::InvalidVarName(_)
+            | Error::UnsupportedVarType { .. }
+            | Error::Repl(_)
+            | Error::InvalidArgument(_) => Status::Usage,
+            Error::ResolvedToml(_)
+            | Error::SignalHandler(_)
+            | Error::Stalled(_)
+            | Error::Write { .. }
+            | Error::ModelFailed(_)
+            | Error::Bmi { .. }
+            | Error::ModelActive => Status::ModelFailed,
+            Error::LibraryNotFound { .. }
+            | Error::LibraryLoad { .. }
+            | Error::MissingSymbol { .. } => Status::BrokenInstall,
+            Error::JuliaInit => Status::JuliaInit,
+            Error::ResultsNotFound(_) | Error::Arrow { .. } | Error::InvalidResults { .. } => {
+                Status::InvalidResults
+            }
+        }
+    }
+}
+
+fn list_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| format!("\n  {}", path.display()))
+        .collect()
+}
@@ -0,0 +1,123 @@
+//! Conversion of the Arrow results to formats that other programs can read.
+
+use std::{
+    error::Error as StdError,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::{metadata::KeyValue, properties::WriterProperties},
+};
+use ribasim::{
+    config::Config,
+    error::Error,
+    results::{Table, RESULTS_FILES},
+};
+
+/// The format of the exported results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Comma separated values, with the metadata in a `#` comment line
+    Csv,
+    /// Parquet with zstd compression, with the metadata as key-value metadata
+    Parquet,
+    /// A JSON object per row per line, without the metadata
+    Jsonl,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+/// ISO 8601 format of the `time` column in CSV, the same as JSON lines uses.
+const ISO_8601: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+/// Export the given results tables, or all that exist, to the output directory, which
+/// defaults to the `results_dir`. Returns the paths that were written.
+pub fn export(
+    config: &Config,
+    format: ExportFormat,
+    tables: &[String],
+    output: Option<&Path>,
+) -> Result<Vec<PathBuf>, Error> {
+    let output = output.map_or_else(|| config.results_path(""), Path::to_owned);
+    let names: Vec<&str> = if tables.is_empty() {
+        RESULTS_FILES.iter().map(|(name, _)| *name).collect()
+    } else {
+        tables.iter().map(String::as_str).collect()
+    };
+
+    let mut written = Vec::new();
+    for name in names {
+        let table = match Table::read_results(config, name) {
+            Ok(table) => table,
+            // Only the basin and flow results are always written
+            Err(Error::ResultsNotFound(_)) if tables.is_empty() => continue,
+            Err(err) => return Err(err),
+        };
+        let path = output.join(format!("{name}.{}", format.extension()));
+        write(&table, format, &path).map_err(|source| Error::Write {
+            path: path.clone(),
+            source,
+        })?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+fn write(
+    table: &Table,
+    format: ExportFormat,
+    path: &Path,
+) -> Result<(), Box<dyn StdError + Send + Sync>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let version = table.metadata("ribasim_version");
+    let mut file = BufWriter::new(File::create(path)?);
+    match format {
+        ExportFormat::Csv => {
+            if let Some(version) = version {
+                writeln!(file, "# ribasim_version: {version}")?;
+            }
+            let mut writer = arrow::csv::WriterBuilder::new()
+                .with_timestamp_format(ISO_8601.to_string())
+                .build(file);
+            writer.write(&table.batch)?;
+            writer.into_inner().flush()?;
+        }
+        ExportFormat::Jsonl => {
+            let mut writer = arrow::json::LineDelimitedWriter::new(file);
+            // Timestamps are written in ISO 8601 already
+            writer.write(&table.batch)?;
+            writer.finish()?;
+            writer.into_inner().flush()?;
+        }
+        ExportFormat::Parquet => {
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                .set_key_value_metadata(version.map(|version| {
+                    vec![KeyValue::new(
+                        "ribasim_version".to_string(),
+                        version.to_string(),
+                    )]
+                }))
+                .build();
+            let mut writer = ArrowWriter::try_new(file, table.batch.schema(), Some(properties))?;
+            writer.write(&table.batch)?;
+            writer.close()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,232 @@
+//! Reading the Node table and other tables from the GeoPackage database of a model.
+
+use std::path::{Path, PathBuf};
+
+use rusqlite::{Connection, OpenFlags};
+
+use crate::error::Error;
+
+/// A row of the Node table, without its geometry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub node_id: i32,
+    pub node_type: String,
+    pub name: String,
+    pub subnetwork_id: Option<i32>,
+}
+
+/// A row of the Edge table, without its geometry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    /// The `fid`, which is the edge ID in the results.
+    pub edge_id: i64,
+    pub from_node_type: String,
+    pub from_node_id: i32,
+    pub to_node_type: String,
+    pub to_node_id: i32,
+    pub edge_type: String,
+    pub name: String,
+    pub subnetwork_id: Option<i32>,
+}
+
+/// A connection to the GeoPackage database, read-only unless opened with `open_writable`.
+pub struct Database {
+    connection: Connection,
+    pub path: PathBuf,
+}
+
+impl Database {
+    /// Open a database without creating it if it does not exist.
+    pub fn open(path: &Path) -> Result<Database, Error> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|source| Error::Database {
+                path: path.to_owned(),
+                source,
+            })?;
+        Ok(Database {
+            connection,
+            path: path.to_owned(),
+        })
+    }
+
+    /// Open an existing database to add or remove tables.
+    pub fn open_writable(path: &Path) -> Result<Database, Error> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+            .map_err(|source| Error::Database {
+                path: path.to_owned(),
+                source,
+            })?;
+        Ok(Database {
+            connection,
+            path: path.to_owned(),
+        })
+    }
+
+    /// The underlying SQLite connection.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Whether a table exists, ignoring case like the Julia core does.
+    pub fn has_table(&self, name: &str) -> Result<bool, Error> {
+        self.connection
+            .query_row(
+                "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1 COLLATE NOCASE",
+                [name],
+                |row| row.get::<_, i64>(0),
+            )
+            .map(|count| count > 0)
+            .map_err(|source| self.error(source))
+    }
+
+    /// All nodes, sorted by node type and node ID.
+    pub fn nodes(&self) -> Result<Vec<Node>, Error> {
+        self.query_nodes(
+            "SELECT node_id, node_type, name, subnetwork_id FROM Node ORDER BY node_type, node_id",
+            [],
+        )
+    }
+
+    /// The nodes of a single type, sorted by node ID like the Julia core sorts its arrays.
+    pub fn nodes_of_type(&self, node_type: &str) -> Result<Vec<Node>, Error> {
+        self.query_nodes(
+            "SELECT node_id, node_type, name, subnetwork_id FROM Node WHERE node_type = ?1 ORDER BY node_id",
+            [node_type],
+        )
+    }
+
+    /// All edges, sorted by `fid` like the Julia core reads them.
+    pub fn edges(&self) -> Result<Vec<Edge>, Error> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT fid, from_node_type, from_node_id, to_node_type, to_node_id, edge_type, name, subnetwork_id FROM Edge ORDER BY fid",
+            )
+            .map_err(|source| self.error(source))?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok(Edge {
+                    edge_id: row.get(0)?,
+                    from_node_type: row.get(1)?,
+                    from_node_id: row.get(2)?,
+                    to_node_type: row.get(3)?,
+                    to_node_id: row.get(4)?,
+                    edge_type: row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+                    name: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+                    subnetwork_id: row.get(7)?,
+                })
+            })
+            .map_err(|source| self.error(source))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|source| self.error(source))
+    }
+
+    /// The number of rows per value of a column, like the nodes per `node_type`.
+    pub fn count_by(&self, table: &str, column: &str) -> Result<Vec<(String, i64)>, Error> {
+        let sql = format!(
+            "SELECT {column}, count(*) FROM {} GROUP BY {column} ORDER BY {column}",
+            quote(table)
+        );
+        let mut statement = self
+            .connection
+            .prepare(&sql)
+            .map_err(|source| self.error(source))?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+                    row.get(1)?,
+                ))
+            })
+            .map_err(|source| self.error(source))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|source| self.error(source))
+    }
+
+    /// The distinct subnetwork IDs of the nodes.
+    pub fn subnetwork_ids(&self) -> Result<Vec<i32>, Error> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT DISTINCT subnetwork_id FROM Node WHERE subnetwork_id IS NOT NULL ORDER BY subnetwork_id",
+            )
+            .map_err(|source| self.error(source))?;
+        let rows = statement
+            .query_map([], |row| row.get(0))
+            .map_err(|source| self.error(source))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|source| self.error(source))
+    }
+
+    /// The names of the tables with model input, leaving out the GeoPackage and SQLite
+    /// bookkeeping tables.
+    pub fn tables(&self) -> Result<Vec<String>, Error> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'gpkg_%' AND name NOT LIKE 'rtree_%' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+            )
+            .map_err(|source| self.error(source))?;
+        let rows = statement
+            .query_map([], |row| row.get(0))
+            .map_err(|source| self.error(source))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|source| self.error(source))
+    }
+
+    /// The number of rows of a table.
+    pub fn row_count(&self, table: &str) -> Result<i64, Error> {
+        self.connection
+            .query_row(
+                &format!("SELECT count(*) FROM {}", quote(table)),
+                [],
+                |row| row.get(0),
+            )
+            .map_err(|source| self.error(source))
+    }
+
+    /// The names and declared types of the columns of a table, in order.
+    pub fn columns(&self, table: &str) -> Result<Vec<(String, String)>, Error> {
+        let mut statement = self
+            .connection
+            .prepare(&format!("PRAGMA table_info({})", quote(table)))
+            .map_err(|source| self.error(source))?;
+        let rows = statement
+            .query_map([], |row| Ok((row.get(1)?, row.get(2)?)))
+            .map_err(|source| self.error(source))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|source| self.error(source))
+    }
+
+    fn query_nodes(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<Node>, Error> {
+        let mut statement = self
+            .connection
+            .prepare(sql)
+            .map_err(|source| self.error(source))?;
+        let rows = statement
+            .query_map(params, |row| {
+                Ok(Node {
+                    node_id: row.get(0)?,
+                    node_type: row.get(1)?,
+                    name: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                    subnetwork_id: row.get(3)?,
+                })
+            })
+            .map_err(|source| self.error(source))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|source| self.error(source))
+    }
+
+    /// Wrap an SQLite error with the path of this database.
+    pub fn error(&self, source: rusqlite::Error) -> Error {
+        Error::Database {
+            path: self.path.clone(),
+            source,
+        }
+    }
+}
+
+/// Quote a table name like `Basin / time` for use in SQL.
+pub fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
@@ -0,0 +1,289 @@
+//! Export of the network in the Node and Edge tables to graph formats.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
+
+use clap::ValueEnum;
+use ribasim::{
+    config::Config,
+    error::Error,
+    geopackage::{Database, Edge, Node},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+    /// GraphML, for Gephi, yEd or networkx
+    Graphml,
+    /// A Mermaid flowchart, for Markdown documents
+    Mermaid,
+}
+
+/// Which part of the network to export.
+pub struct Selection<'a> {
+    pub subnetwork_id: Option<i32>,
+    /// Node IDs to take the neighbourhood of, all nodes if empty.
+    pub around: &'a [i32],
+    /// The number of edges to follow from the `around` nodes, in either direction.
+    pub hops: usize,
+}
+
+type Key<'a> = (&'a str, i32);
+
+fn key(node: &Node) -> Key<'_> {
+    (&node.node_type, node.node_id)
+}
+
+/// Read the network of a model and write it in the given format.
+pub fn export(
+    config: &Config,
+    format: GraphFormat,
+    selection: &Selection,
+) -> Result<String, Error> {
+    let database = Database::open(&config.database_path())?;
+    let nodes = database.nodes()?;
+    let edges = database.edges()?;
+    let (nodes, edges) = select(&nodes, &edges, selection)?;
+    Ok(match format {
+        GraphFormat::Dot => dot(&nodes, &edges),
+        GraphFormat::Graphml => graphml(&nodes, &edges),
+        GraphFormat::Mermaid => mermaid(&nodes, &edges),
+    })
+}
+
+fn select<'a>(
+    nodes: &'a [Node],
+    edges: &'a [Edge],
+    selection: &Selection,
+) -> Result<(Vec<&'a Node>, Vec<&'a Edge>), Error> {
+    let mut kept: BTreeSet<Key> = nodes
+        .iter()
+        .filter(|node| {
+            selection
+                .subnetwork_id
+                .is_none_or(|id| node.subnetwork_id == Some(id))
+        })
+        .map(key)
+        .collect();
+    if let Some(id) = selection.subnetwork_id {
+        if kept.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "no nodes are in subnetwork {id}"
+            )));
+        }
+    }
+    let induced = |kept: &BTreeSet<Key>, edge: &&Edge| {
+        kept.contains(&(edge.from_node_type.as_str(), edge.from_node_id))
+            && kept.contains(&(edge.to_node_type.as_str(), edge.to_node_id))
+    };
+
+    if !selection.around.is_empty() {
+        let mut neighbors: HashMap<Key, Vec<Key>> = HashMap::new();
+        for edge in edges.iter().filter(|edge| induced(&kept, edge)) {
+            let from = (edge.from_node_type.as_str(), edge.from_node_id);
+            let to = (edge.to_node_type.as_str(), edge.to_node_id);
+            neighbors.entry(from).or_default().push(to);
+            neighbors.entry(to).or_default().push(from);
+        }
+        let mut reached: BTreeSet<Key> = kept
+            .iter()
+            .filter(|(_, node_id)| selection.around.contains(node_id))
+            .copied()
+            .collect();
+        if let Some(missing) = selection
+            .around
+            .iter()
+            .find(|id| !reached.iter().any(|(_, node_id)| node_id == *id))
+        {
+            return Err(Error::InvalidArgument(format!(
+                "node #{missing} is not in the selected network"
+            )));
+        }
+        let mut frontier: Vec<Key> = reached.iter().copied().collect();
+        for _ in 0..selection.hops {
+            frontier = frontier
+                .iter()
+                .flat_map(|node| neighbors.get(node).into_iter().flatten())
+                .filter(|node| reached.insert(**node))
+                .copied()
+                .collect();
+        }
+        kept = reached;
+    }
+
+    let nodes = nodes
+        .iter()
+        .filter(|node| kept.contains(&key(node)))
+        .collect();
+    let edges = edges.iter().filter(|edge| induced(&kept, edge)).collect();
+    Ok((nodes, edges))
+}
+
+/// An identifier that all formats accept, like `Basin_1`.
+fn identifier(node_type: &str, node_id: i32) -> String {
+    format!("{node_type}_{node_id}")
+}
+
+fn label(node: &Node) -> String {
+    format!("{} #{}", node.node_type, node.node_id)
+}
+
+/// Appends the text and a newline to `out`.
+fn line(out: &mut String, text: fmt::Arguments) {
+    out.push_str(&text.to_string());
+    out.push('\n');
+}
+
+fn dot(nodes: &[&Node], edges: &[&Edge]) -> String {
+    let escape = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut out = String::from("digraph ribasim {\n    node [shape=box];\n");
+    for node in nodes {
+        let mut text = label(node);
+        if !node.name.is_empty() {
+            text = format!("{text}\\n{}", escape(&node.name));
+        }
+        line(
+            &mut out,
+            format_args!(
+                "    {} [label=\"{text}\"];",
+                identifier(&node.node_type, node.node_id)
+            ),
+        );
+    }
+    for edge in edges {
+        let style = if edge.edge_type == "control" {
+            "style=dashed, color=gray40"
+        } else {
+            "color=blue"
+        };
+        line(
+            &mut out,
+            format_args!(
+                "    {} -> {} [{style}];",
+                identifier(&edge.from_node_type, edge.from_node_id),
+                identifier(&edge.to_node_type, edge.to_node_id)
+            ),
+        );
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn graphml(nodes: &[&Node], edges: &[&Edge]) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let mut out = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="label" for="node" attr.name="label" attr.type="string"/>
+  <key id="node_type" for="node" attr.name="node_type" attr.type="string"/>
+  <key id="node_id" for="node" attr.name="node_id" attr.type="int"/>
+  <key id="name" for="node" attr.name="name" attr.type="string"/>
+  <key id="subnetwork_id" for="node" attr.name="subnetwork_id" attr.type="int"/>
+  <key id="edge_id" for="edge" attr.name="edge_id" attr.type="long"/>
+  <key id="edge_type" for="edge" attr.name="edge_type" attr.type="string"/>
+  <graph id="ribasim" edgedefault="directed">
+"#,
+    );
+    for node in nodes {
+        line(
+            &mut out,
+            format_args!(
+                r#"    <node id="{}">"#,
+                identifier(&node.node_type, node.node_id)
+            ),
+        );
+        line(
+            &mut out,
+            format_args!(r#"      <data key="label">{}</data>"#, escape(&label(node))),
+        );
+        line(
+            &mut out,
+            format_args!(
+                r#"      <data key="node_type">{}</data>"#,
+                escape(&node.node_type)
+            ),
+        );
+        line(
+            &mut out,
+            format_args!(r#"      <data key="node_id">{}</data>"#, node.node_id),
+        );
+        line(
+            &mut out,
+            format_args!(r#"      <data key="name">{}</data>"#, escape(&node.name)),
+        );
+        if let Some(subnetwork_id) = node.subnetwork_id {
+            line(
+                &mut out,
+                format_args!(r#"      <data key="subnetwork_id">{subnetwork_id}</data>"#),
+            );
+        }
+        out.push_str("    </node>\n");
+    }
+    for edge in edges {
+        line(
+            &mut out,
+            format_args!(
+                r#"    <edge id="e{}" source="{}" target="{}">"#,
+                edge.edge_id,
+                identifier(&edge.from_node_type, edge.from_node_id),
+                identifier(&edge.to_node_type, edge.to_node_id)
+            ),
+        );
+        line(
+            &mut out,
+            format_args!(r#"      <data key="edge_id">{}</data>"#, edge.edge_id),
+        );
+        line(
+            &mut out,
+            format_args!(
+                r#"      <data key="edge_type">{}</data>"#,
+                escape(&edge.edge_type)
+            ),
+        );
+        out.push_str("    </edge>\n");
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+fn mermaid(nodes: &[&Node], edges: &[&Edge]) -> String {
+    let escape = |text: &str| text.replace('"', "#quot;");
+    let mut out = String::from("flowchart LR\n");
+    for node in nodes {
+        let mut text = label(node);
+        if !node.name.is_empty() {
+            text = format!("{text}<br>{}", escape(&node.name));
+        }
+        line(
+            &mut out,
+            format_args!(
+                "    {}[\"{text}\"]",
+                identifier(&node.node_type, node.node_id)
+            ),
+        );
+    }
+    for edge in edges {
+        let arrow = if edge.edge_type == "control" {
+            "-.->"
+        } else {
+            "-->"
+        };
+        line(
+            &mut out,
+            format_args!(
+                "    {} {arrow} {}",
+                identifier(&edge.from_node_type, edge.from_node_id),
+                identifier(&edge.to_node_type, edge.to_node_id)
+            ),
+        );
+    }
+    out
+}
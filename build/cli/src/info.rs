@@ -0,0 +1,163 @@
+//! Overview of a model, from its TOML file and GeoPackage database.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use chrono::NaiveDateTime;
+use ribasim::{
+    config::{node_type, Allocation, Config, Solver},
+    error::Error,
+    geopackage::Database,
+};
+use serde::Serialize;
+
+use crate::table;
+
+/// A table in the database besides Node and Edge.
+#[derive(Debug, Serialize)]
+pub struct DatabaseTable {
+    pub name: String,
+    pub rows: i64,
+}
+
+/// A table that is read from an Arrow file given in the TOML.
+#[derive(Debug, Serialize)]
+pub struct ExternalTable {
+    pub name: String,
+    pub path: PathBuf,
+    pub exists: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    pub starttime: NaiveDateTime,
+    pub endtime: NaiveDateTime,
+    pub ribasim_version: String,
+    pub crs: String,
+    pub database: PathBuf,
+    pub solver: Solver,
+    pub allocation: Allocation,
+    pub nodes: BTreeMap<String, i64>,
+    pub edges: BTreeMap<String, i64>,
+    pub subnetwork_ids: Vec<i32>,
+    pub tables: Vec<DatabaseTable>,
+    pub external_tables: Vec<ExternalTable>,
+}
+
+impl Info {
+    pub fn from_config(config: &Config) -> Result<Info, Error> {
+        let database = Database::open(&config.database_path())?;
+        let tables = database
+            .tables()?
+            .into_iter()
+            .filter(|name| !matches!(name.as_str(), "Node" | "Edge"))
+            .map(|name| {
+                let rows = database.row_count(&name)?;
+                Ok(DatabaseTable { name, rows })
+            })
+            .collect::<Result<_, Error>>()?;
+        let external_tables = config
+            .tables
+            .iter()
+            .flat_map(|(section, paths)| {
+                paths.iter().map(move |(kind, path)| {
+                    let path = config.input_path(path);
+                    ExternalTable {
+                        name: format!("{} / {kind}", node_type(section)),
+                        exists: path.is_file(),
+                        path,
+                    }
+                })
+            })
+            .collect();
+
+        Ok(Info {
+            starttime: config.starttime,
+            endtime: config.endtime,
+            ribasim_version: config.ribasim_version.clone(),
+            crs: config.crs.clone(),
+            solver: config.solver.clone(),
+            allocation: config.allocation.clone(),
+            nodes: database
+                .count_by("Node", "node_type")?
+                .into_iter()
+                .collect(),
+            edges: database
+                .count_by("Edge", "edge_type")?
+                .into_iter()
+                .collect(),
+            subnetwork_ids: database.subnetwork_ids()?,
+            tables,
+            external_tables,
+            database: database.path,
+        })
+    }
+
+    pub fn print(&self) {
+        println!("Simulation: {} to {}", self.starttime, self.endtime);
+        println!("Ribasim version: {}", self.ribasim_version);
+        println!("CRS: {}", self.crs);
+        println!("Database: {}", self.database.display());
+        let solver = &self.solver;
+        println!(
+            "Solver: {}, saveat {} s, dt {}, abstol {}, reltol {}",
+            solver.algorithm,
+            solver.saveat,
+            solver
+                .dt
+                .map_or_else(|| "adaptive".to_string(), |dt| format!("{dt} s")),
+            solver.abstol,
+            solver.reltol
+        );
+        if self.allocation.use_allocation {
+            println!("Allocation: every {} s", self.allocation.timestep);
+        }
+
+        println!();
+        let nodes: Vec<Vec<String>> = self
+            .nodes
+            .iter()
+            .map(|(node_type, count)| vec![node_type.clone(), count.to_string()])
+            .collect();
+        table::print(&["node_type", "count"], &nodes);
+
+        println!();
+        let edges: Vec<Vec<String>> = self
+            .edges
+            .iter()
+            .map(|(edge_type, count)| vec![edge_type.clone(), count.to_string()])
+            .collect();
+        table::print(&["edge_type", "count"], &edges);
+
+        if !self.subnetwork_ids.is_empty() {
+            let ids: Vec<String> = self.subnetwork_ids.iter().map(i32::to_string).collect();
+            println!();
+            println!("Subnetworks: {}", ids.join(", "));
+        }
+
+        if !self.tables.is_empty() {
+            println!();
+            let tables: Vec<Vec<String>> = self
+                .tables
+                .iter()
+                .map(|table| vec![table.name.clone(), table.rows.to_string()])
+                .collect();
+            table::print(&["table", "rows"], &tables);
+        }
+
+        if !self.external_tables.is_empty() {
+            println!();
+            let tables: Vec<Vec<String>> = self
+                .external_tables
+                .iter()
+                .map(|table| {
+                    let mut path = table.path.display().to_string();
+                    if !table.exists {
+                        path.push_str(" (missing)");
+                    }
+                    vec![table.name.clone(), path]
+                })
+                .collect();
+            table::print(&["external_table", "path"], &tables);
+        }
+    }
+}
@@ -0,0 +1,15 @@
+//! Rust interface to Ribasim, the water resources model.
+//!
+//! The [`library`] module finds and loads libribasim, and [`bmi::Model`] drives a model
+//! through its Basic Model Interface. The [`config`] module reads the TOML configuration
+//! of a model without needing Julia, [`geopackage`] reads its database and [`results`]
+//! reads the Arrow files a simulation writes. [`schema`] lists the columns of each input
+//! table.
+
+pub mod bmi;
+pub mod config;
+pub mod error;
+pub mod geopackage;
+pub mod library;
+pub mod results;
+pub mod schema;
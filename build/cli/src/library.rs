@@ -0,0 +1,250 @@
+//! Discovery and loading of the libribasim shared library.
+
+use std::{
+    cell::Cell,
+    env,
+    ffi::CString,
+    path::{Path, PathBuf},
+};
+
+use libloading::{Library, Symbol};
+
+use crate::error::Error;
+
+/// File name of the shared library on this platform.
+#[cfg(target_os = "windows")]
+pub const LIB_NAME: &str = "libribasim.dll";
+#[cfg(target_os = "macos")]
+pub const LIB_NAME: &str = "libribasim.dylib";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+pub const LIB_NAME: &str = "libribasim.so";
+
+/// Environment variable that can point to the shared library or its directory.
+const LIB_ENV: &str = "RIBASIM_LIB";
+
+/// The loaded libribasim, with the Julia libraries that were loaded ahead of it.
+pub struct LibRibasim {
+    library: Library,
+    pub path: PathBuf,
+    _dependencies: Vec<Library>,
+    julia_initialized: Cell<bool>,
+    /// Whether libribasim's global model is in use by a [`crate::bmi::Model`].
+    pub(crate) model_active: Cell<bool>,
+}
+
+impl LibRibasim {
+    /// Find and load libribasim.
+    ///
+    /// An explicit `lib` path takes precedence over `RIBASIM_LIB`, and both are used as
+    /// given, without falling back to the search. Otherwise the first existing candidate
+    /// from [`candidates`] is loaded. With `verbose`, every candidate that is tried is
+    /// reported on stderr.
+    pub fn load(lib: Option<&Path>, verbose: bool) -> Result<LibRibasim, Error> {
+        let candidates = match lib {
+            Some(lib) => vec![resolve(lib)],
+            None => match env::var_os(LIB_ENV) {
+                Some(lib) if !lib.is_empty() => vec![resolve(Path::new(&lib))],
+                _ => candidates(),
+            },
+        };
+
+        let path = candidates
+            .iter()
+            .find(|path| {
+                let found = path.is_file();
+                if verbose {
+                    let status = if found { "found" } else { "not found" };
+                    eprintln!("Looking for libribasim at {}: {status}", path.display());
+                }
+                found
+            })
+            .ok_or_else(|| Error::LibraryNotFound {
+                tried: candidates.clone(),
+            })?
+            .clone();
+
+        let dir = path.parent().unwrap_or(Path::new("")).to_owned();
+        let dependencies = prepare_loader(&dir, verbose);
+
+        // Loading libribasim runs its initializers, which are trusted
+        let library = match unsafe { Library::new(&path) } {
+            Ok(library) => library,
+            Err(source) => return Err(Error::LibraryLoad { path, source }),
+        };
+        Ok(LibRibasim {
+            library,
+            path,
+            _dependencies: dependencies,
+            julia_initialized: Cell::new(false),
+            model_active: Cell::new(false),
+        })
+    }
+
+    /// Look up a function exported by libribasim.
+    ///
+    /// # Safety
+    ///
+    /// `T` must match the signature of the exported function.
+    pub unsafe fn get<T>(&self, symbol: &'static str) -> Result<Symbol<'_, T>, Error> {
+        self.library
+            .get(symbol.as_bytes())
+            .map_err(|source| Error::MissingSymbol {
+                symbol,
+                path: self.path.clone(),
+                source,
+            })
+    }
+
+    /// Start the Julia runtime, which must happen before calling into Ribasim.
+    ///
+    /// Julia is only initialized on the first call, later calls do nothing.
+    pub fn init_julia(&self) -> Result<(), Error> {
+        if self.julia_initialized.get() {
+            return Ok(());
+        }
+        unsafe {
+            // PackageCompiler's `init_julia` returns nothing, even if it failed
+            let init_julia: Symbol<unsafe extern "C" fn(i32, *const libc::c_char)> =
+                self.get("init_julia")?;
+            init_julia(0, CString::default().as_ptr());
+
+            // libjulia is a dependency of libribasim, so its symbols are found as well
+            if let Ok(is_initialized) =
+                self.get::<unsafe extern "C" fn() -> i32>("jl_is_initialized")
+            {
+                if is_initialized() == 0 {
+                    return Err(Error::JuliaInit);
+                }
+            }
+        }
+        self.julia_initialized.set(true);
+        Ok(())
+    }
+
+    /// Run a model with `Ribasim.main`, returning its exit code.
+    pub fn execute(&self, toml_path: &Path) -> Result<i32, Error> {
+        let toml_path = path_to_cstring(toml_path)?;
+        unsafe {
+            let execute: Symbol<unsafe extern "C" fn(*const libc::c_char) -> i32> =
+                self.get("execute")?;
+            Ok(execute(toml_path.as_ptr()))
+        }
+    }
+}
+
+/// Convert a path to a C string for the libribasim API, which expects UTF-8.
+pub fn path_to_cstring(path: &Path) -> Result<CString, Error> {
+    path.to_str()
+        .and_then(|path| CString::new(path).ok())
+        .ok_or_else(|| Error::InvalidPathEncoding(path.to_owned()))
+}
+
+/// Allow pointing to the directory that contains the shared library.
+fn resolve(lib: &Path) -> PathBuf {
+    if lib.is_dir() {
+        lib.join(LIB_NAME)
+    } else {
+        lib.to_owned()
+    }
+}
+
+/// The ordered list of locations that are searched for the shared library.
+///
+/// First the layouts relative to the executable, as produced by build.jl and by
+/// installing into a prefix, then the directories on the loader path, and finally the
+/// standard system locations.
+pub fn candidates() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if let Some(exe_dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_owned))
+    {
+        if cfg!(target_os = "windows") {
+            dirs.push(exe_dir.join("bin"));
+            dirs.push(exe_dir.clone());
+        } else {
+            dirs.push(exe_dir.join("lib"));
+            dirs.push(exe_dir.join("../lib"));
+            dirs.push(exe_dir.join("../lib/ribasim"));
+            dirs.push(exe_dir.clone());
+        }
+    }
+
+    let loader_path = if cfg!(target_os = "windows") {
+        "PATH"
+    } else if cfg!(target_os = "macos") {
+        "DYLD_LIBRARY_PATH"
+    } else {
+        "LD_LIBRARY_PATH"
+    };
+    if let Some(paths) = env::var_os(loader_path) {
+        dirs.extend(env::split_paths(&paths).filter(|dir| !dir.as_os_str().is_empty()));
+    }
+
+    if !cfg!(target_os = "windows") {
+        for dir in [
+            "/usr/local/lib/ribasim",
+            "/usr/local/lib",
+            "/usr/lib/ribasim",
+            "/usr/lib",
+            "/opt/ribasim/lib",
+        ] {
+            dirs.push(PathBuf::from(dir));
+        }
+    }
+
+    dirs.into_iter().map(|dir| dir.join(LIB_NAME)).collect()
+}
+
+/// Make sure the libraries that libribasim depends on can be found.
+///
+/// On Windows the directory of the DLL is put on the `PATH`, so its Julia DLLs resolve.
+#[cfg(target_os = "windows")]
+fn prepare_loader(dir: &Path, _verbose: bool) -> Vec<Library> {
+    env::set_var(
+        "PATH",
+        format!("{};{}", dir.display(), env::var("PATH").unwrap_or_default()),
+    );
+    Vec::new()
+}
+
+/// Make sure the libraries that libribasim depends on can be found.
+///
+/// The dynamic loader only reads `LD_LIBRARY_PATH` at process startup, so setting it
+/// here would have no effect. Instead the Julia runtime that is shipped next to
+/// libribasim is loaded first with `RTLD_GLOBAL`, such that libribasim's dependency on
+/// it is satisfied by soname, regardless of the RUNPATH the library was built with.
+#[cfg(not(target_os = "windows"))]
+fn prepare_loader(dir: &Path, verbose: bool) -> Vec<Library> {
+    use libloading::os::unix::{Library as UnixLibrary, RTLD_GLOBAL, RTLD_NOW};
+
+    let names: &[&str] = if cfg!(target_os = "macos") {
+        &["libjulia.1.dylib"]
+    } else {
+        &["libjulia.so.1"]
+    };
+
+    let mut dependencies = Vec::new();
+    for name in names {
+        let path = dir.join(name);
+        if !path.is_file() {
+            continue;
+        }
+        // Loading the Julia runtime that belongs to libribasim is trusted
+        match unsafe { UnixLibrary::open(Some(&path), RTLD_NOW | RTLD_GLOBAL) } {
+            Ok(library) => {
+                if verbose {
+                    eprintln!("Preloaded {}", path.display());
+                }
+                dependencies.push(library.into());
+            }
+            Err(err) => {
+                if verbose {
+                    eprintln!("Failed to preload {}: {err}", path.display());
+                }
+            }
+        }
+    }
+    dependencies
+}
@@ -1,68 +1,1189 @@
+mod aggregate;
+mod allocation;
+mod archive;
+mod check;
+mod compare;
+mod control;
+mod coverage;
+mod export;
+mod graph;
+mod info;
+mod migrate;
+mod overrides;
+mod progress;
+mod query;
+mod repl;
+mod run;
+mod summary;
+mod table;
+mod tables;
+mod validate;
+
 use std::{
-    env::{self, consts::OS},
-    ffi::CString,
-    path::PathBuf,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
 };
 
-use clap::Parser;
-use libloading::{Library, Symbol};
-use std::process::ExitCode;
+use aggregate::{Period, Stat};
+use chrono::NaiveDateTime;
+use clap::{builder::PossibleValuesParser, Args, Parser, Subcommand, ValueEnum};
+use export::ExportFormat;
+use graph::GraphFormat;
+use progress::ProgressFormat;
+use query::QueryFormat;
+use ribasim::{
+    config::{parse_datetime, Config, ConfigError},
+    error::{Error, Status, EXIT_CODES_HELP},
+    library::LibRibasim,
+    results::RESULTS_FILES,
+    schema::{self, Schema, SCHEMAS},
+};
+use tables::ArrowCompression;
 
 #[derive(Parser)]
-#[command(version)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    arg_required_else_help = true,
+    after_help = EXIT_CODES_HELP
+)]
 struct Cli {
     /// Path to the TOML file
-    toml_path: PathBuf,
+    toml_path: Option<PathBuf>,
+
+    #[command(flatten)]
+    run: RunOptions,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Args)]
+struct RunOptions {
+    #[command(flatten)]
+    overrides: Overrides,
+
+    #[command(flatten)]
+    library: LibraryArgs,
+
+    /// Drive the model step by step through the BMI, so that Ctrl-C stops the simulation
+    /// and still writes the results
+    ///
+    /// Unlike a normal run, this writes no ribasim.log, ignores the [logging] settings and
+    /// does not warn if the ribasim_version of the model differs.
+    #[arg(long)]
+    stepwise: bool,
+
+    /// Report progress on stderr, implies --stepwise [default: bar if stderr is a terminal]
+    #[arg(long, value_name = "FORMAT")]
+    progress: Option<ProgressFormat>,
+
+    /// Seconds between the plain and JSON progress records
+    #[arg(long, value_name = "SECONDS", default_value_t = 10.0)]
+    progress_interval: f64,
+
+    /// After a successful run, fail if the absolute relative_error of any basin and time
+    /// step in basin.arrow exceeds this
+    #[arg(long, value_name = "FRACTION")]
+    max_relative_error: Option<f64>,
+
+    /// After a successful run, fail if the absolute balance_error of any basin and time
+    /// step in basin.arrow exceeds this
+    #[arg(long, value_name = "M3/S")]
+    max_balance_error: Option<f64>,
+}
+
+#[derive(Args)]
+struct Overrides {
+    /// Override a TOML key, e.g. `--set solver.dt=60`, can be repeated
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    set: Vec<String>,
+}
+
+#[derive(Args)]
+struct LibraryArgs {
+    /// Path to libribasim, or the directory containing it [env: RIBASIM_LIB]
+    #[arg(long, value_name = "PATH")]
+    lib: Option<PathBuf>,
+
+    /// Report which paths were tried to find libribasim
+    #[arg(long)]
+    verbose: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run models, like passing the TOML file without a subcommand
+    ///
+    /// Julia is initialized once for all models, which are run one after the other.
+    Run {
+        /// Paths to the TOML files
+        #[arg(required_unless_present = "from_file")]
+        toml_paths: Vec<PathBuf>,
+
+        /// Read more TOML paths from a file, one per line, relative to the file, skipping
+        /// empty lines and lines starting with #
+        #[arg(long, value_name = "FILE")]
+        from_file: Option<PathBuf>,
+
+        #[command(flatten)]
+        options: RunOptions,
+    },
+
+    /// Open an interactive shell to step through a model and inspect its state
+    Bmi {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        #[command(flatten)]
+        overrides: Overrides,
+
+        #[command(flatten)]
+        library: LibraryArgs,
+    },
+
+    /// Compare the results of two simulations, failing if they differ
+    Compare {
+        /// Results directory, or TOML file whose results_dir is used
+        a: PathBuf,
+
+        /// Results directory, or TOML file whose results_dir is used, to compare against
+        b: PathBuf,
+
+        /// Results tables to compare, all that exist by default
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_parser = PossibleValuesParser::new(RESULTS_FILES.map(|(name, _)| name))
+        )]
+        tables: Vec<String>,
+
+        /// Absolute tolerance of numeric columns
+        #[arg(long, default_value_t = 1e-8)]
+        atol: f64,
+
+        /// Relative tolerance of numeric columns, relative to the values of B
+        #[arg(long, default_value_t = 1e-5)]
+        rtol: f64,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+
+    /// Check the network topology in the Node and Edge tables without starting Julia
+    Check {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        #[command(flatten)]
+        overrides: Overrides,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+
+    /// Check that the time tables of the input cover the simulation period
+    Coverage {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        #[command(flatten)]
+        overrides: Overrides,
+
+        /// Report gaps between timestamps longer than this
+        #[arg(long, value_name = "SECONDS")]
+        max_gap: Option<f64>,
+
+        /// Print all time series, not only those that do not cover the period
+        #[arg(long)]
+        all: bool,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+
+    /// Work with the network of nodes and edges
+    Graph {
+        #[command(subcommand)]
+        command: GraphCommand,
+    },
+
+    /// Give an overview of the nodes, edges, tables and settings of a model
+    Info {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        #[command(flatten)]
+        overrides: Overrides,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+
+    /// Migrate a model written for an older Ribasim version to this version
+    Migrate {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        /// Print the changes without making them
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Pack the TOML, database and Arrow input files of a model into one archive
+    Pack {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        /// Archive to write, a .zip or .tar.zst file
+        #[arg(long, short, value_name = "FILE")]
+        output: PathBuf,
+    },
+
+    /// Unpack a model archive and verify its checksums
+    Unpack {
+        /// Archive written by `ribasim pack`
+        archive: PathBuf,
+
+        /// Directory to unpack into [default: the archive name without extension]
+        #[arg(long, short, value_name = "DIR")]
+        output: Option<PathBuf>,
+    },
+
+    /// Analyze the results of a simulation
+    Results {
+        #[command(subcommand)]
+        command: ResultsCommand,
+    },
+
+    /// List the input tables and their columns, as the Julia core expects them
+    Schema {
+        #[command(subcommand)]
+        command: SchemaCommand,
+    },
+
+    /// Move input tables between the database and Arrow files
+    Tables {
+        #[command(subcommand)]
+        command: TablesCommand,
+    },
+
+    /// Check a model without starting Julia
+    Validate {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+}
+
+#[derive(Subcommand)]
+enum GraphCommand {
+    /// Write the network as DOT, GraphML or a Mermaid flowchart
+    Export {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        #[command(flatten)]
+        overrides: Overrides,
+
+        #[arg(long, value_enum)]
+        format: GraphFormat,
+
+        /// Only export the nodes of this subnetwork
+        #[arg(long, value_name = "ID")]
+        subnetwork: Option<i32>,
+
+        /// Only export the neighbourhood of these node IDs
+        #[arg(long, value_name = "ID", value_delimiter = ',')]
+        around: Vec<i32>,
+
+        /// Number of edges to follow from the --around nodes
+        #[arg(long, default_value_t = 1, requires = "around")]
+        hops: usize,
+
+        /// File to write to [default: stdout]
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SchemaCommand {
+    /// List all input tables
+    List {
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+
+    /// Show the columns of an input table
+    Show {
+        /// Table, like basin.profile or ribasim.basin.profile
+        #[arg(value_parser = parse_schema)]
+        table: &'static Schema,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum TablesCommand {
+    /// Move a table from the database to an Arrow file and refer to it from the TOML
+    Extract {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        /// Table to move, like basin.time
+        #[arg(value_parser = parse_schema)]
+        table: &'static Schema,
+
+        /// Path of the Arrow file relative to the input_dir [default: SECTION/KIND.arrow]
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<String>,
+
+        #[arg(long, value_enum, default_value_t = ArrowCompression::None)]
+        compression: ArrowCompression,
+
+        /// Dictionary encode text columns with repeated values, like node_type
+        #[arg(long)]
+        dictionary: bool,
+    },
+
+    /// Move a table from its Arrow file into the database and remove it from the TOML
+    Embed {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        /// Table to move, like basin.time
+        #[arg(value_parser = parse_schema)]
+        table: &'static Schema,
+    },
+}
+
+#[derive(Subcommand)]
+enum ResultsCommand {
+    /// Per-basin water balance totals and the worst relative error
+    Summary {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        #[command(flatten)]
+        overrides: Overrides,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+
+    /// Report the shortages of the demands in the allocation results
+    Allocation {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        #[command(flatten)]
+        overrides: Overrides,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+
+    /// Show the DiscreteControl state timeline and flag chattering
+    Control {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        #[command(flatten)]
+        overrides: Overrides,
+
+        /// DiscreteControl node IDs, all by default
+        #[arg(long = "node", value_name = "ID", value_delimiter = ',')]
+        nodes: Vec<i32>,
+
+        /// Print the periods spent in each control state
+        #[arg(long)]
+        timeline: bool,
+
+        /// Flag nodes that switch more often than this within the window
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        max_switches: usize,
+
+        /// Length of the chattering window in seconds
+        #[arg(long, value_name = "SECONDS", default_value_t = 86400.0)]
+        window: f64,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+
+    /// Aggregate the results over days, months or years into new Arrow files
+    Aggregate {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        #[command(flatten)]
+        overrides: Overrides,
+
+        #[arg(long, value_enum)]
+        period: Period,
+
+        #[arg(long, value_enum)]
+        stat: Stat,
+
+        /// First month of the hydrological year
+        #[arg(long, value_name = "MONTH", default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=12))]
+        hydro_year_start: u32,
+
+        /// Results tables to aggregate, all that exist except control by default
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_parser = PossibleValuesParser::new(RESULTS_FILES.map(|(name, _)| name))
+        )]
+        tables: Vec<String>,
+
+        /// Directory to write to [default: the results_dir]
+        #[arg(long, short, value_name = "DIR")]
+        output: Option<PathBuf>,
+    },
+
+    /// Print the time series of selected nodes or edges
+    Query {
+        /// Results table to query
+        #[arg(value_parser = PossibleValuesParser::new(RESULTS_FILES.map(|(name, _)| name)))]
+        table: String,
+
+        /// Path to the TOML file
+        #[arg(default_value = "ribasim.toml")]
+        toml_path: PathBuf,
+
+        #[command(flatten)]
+        overrides: Overrides,
+
+        /// Node IDs, for flow tables matching either end of the edge
+        #[arg(long = "node", value_name = "ID", value_delimiter = ',')]
+        nodes: Vec<i32>,
+
+        /// Node type, like Basin or UserDemand
+        #[arg(long)]
+        node_type: Option<String>,
+
+        /// Edge IDs
+        #[arg(long = "edge", value_name = "ID", value_delimiter = ',')]
+        edges: Vec<i32>,
+
+        /// Subgrid IDs
+        #[arg(long = "subgrid", value_name = "ID", value_delimiter = ',')]
+        subgrids: Vec<i32>,
+
+        /// Columns to print besides the keys, all by default
+        #[arg(long = "var", value_name = "COLUMN", value_delimiter = ',')]
+        vars: Vec<String>,
+
+        /// First time to include, a date or date-time
+        #[arg(long, value_parser = parse_datetime_arg)]
+        from: Option<NaiveDateTime>,
+
+        /// Last time to include, a date or date-time
+        #[arg(long, value_parser = parse_datetime_arg)]
+        to: Option<NaiveDateTime>,
+
+        #[arg(long, value_enum, default_value_t = QueryFormat::Table)]
+        format: QueryFormat,
+    },
+
+    /// Convert the Arrow results to CSV, Parquet or JSON lines
+    Export {
+        /// Path to the TOML file
+        toml_path: PathBuf,
+
+        #[command(flatten)]
+        overrides: Overrides,
+
+        #[arg(long, value_enum)]
+        format: ExportFormat,
+
+        /// Results tables to export, all that exist by default
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_parser = PossibleValuesParser::new(RESULTS_FILES.map(|(name, _)| name))
+        )]
+        tables: Vec<String>,
+
+        /// Directory to write to [default: the results_dir]
+        #[arg(long, short, value_name = "DIR")]
+        output: Option<PathBuf>,
+    },
+}
+
+/// How a report is printed on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Aligned columns for reading
+    Table,
+    /// A JSON document for other programs
+    Json,
 }
 
 fn main() -> ExitCode {
-    // Get the path to the directory containing the current executable
-    let exe_dir = env::current_exe().unwrap().parent().unwrap().to_owned();
-
-    // Set the appropriate environment variable for the current platform
-    if OS == "windows" {
-        env::set_var(
-            "PATH",
-            format!(
-                "{};{}",
-                exe_dir.join("bin").display(),
-                env::var("PATH").unwrap_or_default()
-            ),
+    // Parse command line arguments
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Some(Command::Bmi {
+            toml_path,
+            overrides,
+            library,
+        }) => repl::repl(&toml_path, &overrides.set, &library),
+        Some(Command::Compare {
+            a,
+            b,
+            tables,
+            atol,
+            rtol,
+            format,
+        }) => compare(&a, &b, &tables, compare::Tolerance { atol, rtol }, format),
+        Some(Command::Check {
+            toml_path,
+            overrides,
+            format,
+        }) => check(&toml_path, &overrides.set, format),
+        Some(Command::Coverage {
+            toml_path,
+            overrides,
+            max_gap,
+            all,
+            format,
+        }) => coverage(&toml_path, &overrides.set, max_gap, all, format),
+        Some(Command::Info {
+            toml_path,
+            overrides,
+            format,
+        }) => info(&toml_path, &overrides.set, format),
+        Some(Command::Graph { command }) => graph(command),
+        Some(Command::Migrate { toml_path, dry_run }) => migrate(&toml_path, dry_run),
+        Some(Command::Pack { toml_path, output }) => pack(&toml_path, &output),
+        Some(Command::Unpack { archive, output }) => unpack(&archive, output),
+        Some(Command::Results { command }) => results(command),
+        Some(Command::Schema { command }) => schema(command),
+        Some(Command::Tables { command }) => tables(command),
+        Some(Command::Validate {
+            toml_path,
+            overrides,
+        }) => validate(&toml_path, &overrides.set),
+        Some(Command::Run {
+            mut toml_paths,
+            from_file,
+            options,
+        }) => match from_file.map(|path| read_list(&path)).transpose() {
+            Ok(listed) => {
+                toml_paths.extend(listed.into_iter().flatten());
+                run(&toml_paths, &options)
+            }
+            Err(err) => Err(err),
+        },
+        None => run(
+            &[cli.toml_path.expect("clap requires a TOML path")],
+            &cli.run,
+        ),
+    };
+
+    match result {
+        Ok(status) => status.into(),
+        Err(err) => {
+            eprintln!("error: {err}");
+            err.status().into()
+        }
+    }
+}
+
+/// Check the network topology, reporting all violations.
+fn check(toml_path: &Path, set: &[String], format: OutputFormat) -> Result<Status, Error> {
+    let config = load_config(toml_path, set)?;
+    let violations = check::check(&config)?;
+    match format {
+        OutputFormat::Json => print_json(&violations),
+        OutputFormat::Table if violations.is_empty() => {
+            println!("{} passed all network checks", toml_path.display());
+        }
+        OutputFormat::Table => {
+            for violation in &violations {
+                eprintln!("error: {}", violation.message);
+            }
+            eprintln!(
+                "Found {} error(s) in the network of {}",
+                violations.len(),
+                toml_path.display()
+            );
+        }
+    }
+    if violations.is_empty() {
+        Ok(Status::Success)
+    } else {
+        Ok(Status::InvalidModel)
+    }
+}
+
+/// Check the time tables against the simulation period, failing if any does not cover it.
+fn coverage(
+    toml_path: &Path,
+    set: &[String],
+    max_gap: Option<f64>,
+    all: bool,
+    format: OutputFormat,
+) -> Result<Status, Error> {
+    let config = load_config(toml_path, set)?;
+    let report = coverage::CoverageReport::from_config(&config, max_gap)?;
+    match format {
+        OutputFormat::Table => report.print(all),
+        OutputFormat::Json => print_json(&report),
+    }
+    if report.failures().next().is_none() {
+        Ok(Status::Success)
+    } else {
+        Ok(Status::InvalidModel)
+    }
+}
+
+fn info(toml_path: &Path, set: &[String], format: OutputFormat) -> Result<Status, Error> {
+    let config = load_config(toml_path, set)?;
+    let info = info::Info::from_config(&config)?;
+    match format {
+        OutputFormat::Table => info.print(),
+        OutputFormat::Json => print_json(&info),
+    }
+    Ok(Status::Success)
+}
+
+/// Check the model configuration and referenced input files, reporting all errors.
+fn validate(toml_path: &Path, set: &[String]) -> Result<Status, Error> {
+    let errors = match overrides::load(toml_path, set) {
+        Ok((config, _)) => validate::validate(&config),
+        Err(ConfigError::Invalid(_, errors)) => errors,
+        Err(err) => return Err(err.into()),
+    };
+
+    if errors.is_empty() {
+        println!("{} is valid", toml_path.display());
+        return Ok(Status::Success);
+    }
+    for error in &errors {
+        eprintln!("error: {error}");
+    }
+    eprintln!("Found {} error(s) in {}", errors.len(), toml_path.display());
+    Ok(Status::InvalidModel)
+}
+
+fn graph(command: GraphCommand) -> Result<Status, Error> {
+    match command {
+        GraphCommand::Export {
+            toml_path,
+            overrides,
+            format,
+            subnetwork,
+            around,
+            hops,
+            output,
+        } => {
+            let config = load_config(&toml_path, &overrides.set)?;
+            let text = graph::export(
+                &config,
+                format,
+                &graph::Selection {
+                    subnetwork_id: subnetwork,
+                    around: &around,
+                    hops,
+                },
+            )?;
+            match output {
+                Some(path) => fs::write(&path, text).map_err(|source| Error::Write {
+                    path,
+                    source: source.into(),
+                })?,
+                None => print!("{text}"),
+            }
+            Ok(Status::Success)
+        }
+    }
+}
+
+fn migrate(toml_path: &Path, dry_run: bool) -> Result<Status, Error> {
+    if !toml_path.is_file() {
+        return Err(Error::TomlNotFound(toml_path.to_owned()));
+    }
+    let report = migrate::migrate(toml_path, dry_run)?;
+    if report.from == report.to {
+        println!(
+            "{} is already for Ribasim {}",
+            toml_path.display(),
+            report.to
         );
+        return Ok(Status::Success);
     }
 
-    // TODO: Do we need to set LD_LIBRARY_PATH on linux?
+    println!(
+        "Migrating {} from Ribasim {} to {}",
+        toml_path.display(),
+        report.from,
+        report.to
+    );
+    for (migration, lines) in &report.steps {
+        println!();
+        println!("{}: {}", migration.version, migration.description);
+        if lines.is_empty() {
+            println!(" (nothing to change)");
+        }
+        for line in lines {
+            println!("{line}");
+        }
+    }
+    println!();
+    println!("--- {}", toml_path.display());
+    println!("+++ {}", toml_path.display());
+    for line in &report.toml_diff {
+        println!("{line}");
+    }
+    println!();
+    if dry_run {
+        println!("Dry run, no files were changed");
+    } else {
+        for backup in &report.backups {
+            println!("Kept the original as {}", backup.display());
+        }
+        println!(
+            "Check the migrated model with `ribasim validate {}`",
+            toml_path.display()
+        );
+    }
+    Ok(Status::Success)
+}
 
-    // Parse command line arguments
-    let cli = Cli::parse();
+fn pack(toml_path: &Path, output: &Path) -> Result<Status, Error> {
+    if !toml_path.is_file() {
+        return Err(Error::TomlNotFound(toml_path.to_owned()));
+    }
+    let manifest = archive::pack(toml_path, output)?;
+    for file in &manifest.files {
+        println!("{}  {}", file.sha256, file.path);
+    }
+    println!("Wrote {}", output.display());
+    Ok(Status::Success)
+}
+
+fn unpack(archive: &Path, output: Option<PathBuf>) -> Result<Status, Error> {
+    let dir = output.unwrap_or_else(|| {
+        let name = archive
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().to_string());
+        let stem = [".zip", ".tar.zst", ".tzst"]
+            .iter()
+            .find_map(|extension| name.strip_suffix(extension))
+            .unwrap_or(&name);
+        PathBuf::from(stem)
+    });
+    let (toml_path, manifest) = archive::unpack(archive, &dir)?;
+    println!(
+        "Verified {} files, run the model with `ribasim {}`",
+        manifest.files.len(),
+        toml_path.display()
+    );
+    let version = env!("CARGO_PKG_VERSION");
+    if manifest.ribasim_version != version {
+        eprintln!(
+            "warning: the model is for Ribasim {}, this is Ribasim {version}",
+            manifest.ribasim_version
+        );
+    }
+    Ok(Status::Success)
+}
+
+fn schema(command: SchemaCommand) -> Result<Status, Error> {
+    match command {
+        SchemaCommand::List { format } => match format {
+            OutputFormat::Json => print_json(&SCHEMAS.as_slice()),
+            OutputFormat::Table => {
+                let rows: Vec<Vec<String>> = SCHEMAS
+                    .iter()
+                    .map(|schema| {
+                        let columns: Vec<&str> =
+                            schema.columns.iter().map(|column| column.name).collect();
+                        vec![
+                            format!("{}.{}", schema.section, schema.kind),
+                            schema.table_name(),
+                            columns.join(", "),
+                        ]
+                    })
+                    .collect();
+                table::print(&["table", "database_table", "columns"], &rows);
+            }
+        },
+        SchemaCommand::Show { table, format } => match format {
+            OutputFormat::Json => print_json(table),
+            OutputFormat::Table => {
+                println!("{}", table.name());
+                println!(
+                    "The {} table in the database, or [{}] {} in the TOML",
+                    table.table_name(),
+                    table.section,
+                    table.kind
+                );
+                println!();
+                let rows: Vec<Vec<String>> = table
+                    .columns
+                    .iter()
+                    .map(|column| {
+                        vec![
+                            column.name.to_string(),
+                            column.column_type.to_string(),
+                            if column.nullable { "yes" } else { "no" }.to_string(),
+                        ]
+                    })
+                    .collect();
+                table::print(&["column", "type", "nullable"], &rows);
+            }
+        },
+    }
+    Ok(Status::Success)
+}
+
+fn tables(command: TablesCommand) -> Result<Status, Error> {
+    match command {
+        TablesCommand::Extract {
+            toml_path,
+            table,
+            output,
+            compression,
+            dictionary,
+        } => {
+            if !toml_path.is_file() {
+                return Err(Error::TomlNotFound(toml_path));
+            }
+            let path = tables::extract(
+                &toml_path,
+                table,
+                output.as_deref(),
+                compression,
+                dictionary,
+            )?;
+            println!("Moved {} to {}", table.table_name(), path.display());
+        }
+        TablesCommand::Embed { toml_path, table } => {
+            if !toml_path.is_file() {
+                return Err(Error::TomlNotFound(toml_path));
+            }
+            let path = tables::embed(&toml_path, table)?;
+            println!(
+                "Moved {} into the database, {} is no longer used",
+                table.table_name(),
+                path.display()
+            );
+        }
+    }
+    Ok(Status::Success)
+}
+
+fn results(command: ResultsCommand) -> Result<Status, Error> {
+    match command {
+        ResultsCommand::Summary {
+            toml_path,
+            overrides,
+            format,
+        } => {
+            let config = load_config(&toml_path, &overrides.set)?;
+            let summary = summary::Summary::from_results(&config)?;
+            match format {
+                OutputFormat::Table => summary.print(),
+                OutputFormat::Json => print_json(&summary),
+            }
+            Ok(Status::Success)
+        }
+        ResultsCommand::Allocation {
+            toml_path,
+            overrides,
+            format,
+        } => {
+            let config = load_config(&toml_path, &overrides.set)?;
+            let report = allocation::AllocationReport::from_results(&config)?;
+            match format {
+                OutputFormat::Table => report.print(),
+                OutputFormat::Json => print_json(&report),
+            }
+            Ok(Status::Success)
+        }
+        ResultsCommand::Control {
+            toml_path,
+            overrides,
+            nodes,
+            timeline,
+            max_switches,
+            window,
+            format,
+        } => {
+            let config = load_config(&toml_path, &overrides.set)?;
+            let report =
+                control::ControlReport::from_results(&config, &nodes, window, max_switches)?;
+            match format {
+                OutputFormat::Table => report.print(timeline),
+                OutputFormat::Json => print_json(&report),
+            }
+            Ok(Status::Success)
+        }
+        ResultsCommand::Aggregate {
+            toml_path,
+            overrides,
+            period,
+            stat,
+            hydro_year_start,
+            tables,
+            output,
+        } => {
+            let config = load_config(&toml_path, &overrides.set)?;
+            for path in aggregate::aggregate(
+                &config,
+                &tables,
+                period,
+                stat,
+                hydro_year_start,
+                output.as_deref(),
+            )? {
+                println!("Wrote {}", path.display());
+            }
+            Ok(Status::Success)
+        }
+        ResultsCommand::Query {
+            table,
+            toml_path,
+            overrides,
+            nodes,
+            node_type,
+            edges,
+            subgrids,
+            vars,
+            from,
+            to,
+            format,
+        } => {
+            let config = load_config(&toml_path, &overrides.set)?;
+            let batch = query::query(
+                &config,
+                &query::Query {
+                    table: &table,
+                    nodes: &nodes,
+                    node_type: node_type.as_deref(),
+                    edges: &edges,
+                    subgrids: &subgrids,
+                    vars: &vars,
+                    from,
+                    to,
+                },
+            )?;
+            query::print(&batch, format)?;
+            Ok(Status::Success)
+        }
+        ResultsCommand::Export {
+            toml_path,
+            overrides,
+            format,
+            tables,
+            output,
+        } => {
+            let config = load_config(&toml_path, &overrides.set)?;
+            for path in export::export(&config, format, &tables, output.as_deref())? {
+                println!("Wrote {}", path.display());
+            }
+            Ok(Status::Success)
+        }
+    }
+}
 
-    if !cli.toml_path.is_file() {
-        eprintln!("File not found {:?}", cli.toml_path);
-        return ExitCode::FAILURE;
+fn compare(
+    a: &Path,
+    b: &Path,
+    tables: &[String],
+    tolerance: compare::Tolerance,
+    format: OutputFormat,
+) -> Result<Status, Error> {
+    let comparison = compare::compare(&results_dir(a)?, &results_dir(b)?, tables, tolerance)?;
+    match format {
+        OutputFormat::Table => comparison.print(),
+        OutputFormat::Json => print_json(&comparison),
+    }
+    if comparison.passed() {
+        Ok(Status::Success)
+    } else {
+        Ok(Status::ResultsDiffer)
     }
+}
 
-    let shared_lib_path = match OS {
-        "windows" => exe_dir.join("bin/libribasim.dll"),
-        "linux" => exe_dir.join("lib/libribasim.so"),
-        "macos" => exe_dir.join("lib/libribasim.dylib"),
-        _ => unimplemented!("Your OS is not supported yet."),
+/// The path itself if it is a directory, or the results_dir of a TOML file.
+fn results_dir(path: &Path) -> Result<PathBuf, Error> {
+    if path.is_dir() {
+        return Ok(path.to_owned());
+    }
+    if !path.is_file() {
+        return Err(Error::ResultsNotFound(path.to_owned()));
+    }
+    let config = load_config(path, &[])?;
+    Ok(config.results_path(""))
+}
+
+fn parse_schema(text: &str) -> Result<&'static Schema, String> {
+    schema::find(text)
+        .ok_or_else(|| "expected a table like basin.time, see `ribasim schema list`".to_string())
+}
+
+fn parse_datetime_arg(text: &str) -> Result<NaiveDateTime, String> {
+    parse_datetime(text).ok_or_else(|| "expected a date like 2020-01-01 or a date-time".to_string())
+}
+
+/// Read the configuration of a model, with the `--set` overrides applied.
+fn load_config(toml_path: &Path, set: &[String]) -> Result<Config, Error> {
+    if !toml_path.is_file() {
+        return Err(Error::TomlNotFound(toml_path.to_owned()));
+    }
+    Ok(overrides::load(toml_path, set)?.0)
+}
+
+fn print_json(value: &impl serde::Serialize) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("reports serialize to JSON")
+    );
+}
+
+/// The TOML paths in a list file, relative to the directory of the file.
+fn read_list(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let text = fs::read_to_string(path).map_err(|err| {
+        Error::InvalidArgument(format!("Failed to read {}: {err}", path.display()))
+    })?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| dir.join(line))
+        .collect())
+}
+
+/// Run the models one after the other in the same Julia session, and report the status
+/// and duration of each if there are several. Returns the status of the first model that
+/// did not succeed.
+fn run(toml_paths: &[PathBuf], options: &RunOptions) -> Result<Status, Error> {
+    if toml_paths.is_empty() {
+        return Err(Error::InvalidArgument(
+            "the list of TOML files is empty".to_string(),
+        ));
+    }
+    // Fail before the slow start of Julia, a batch reports missing models in its table
+    if let [toml_path] = toml_paths {
+        if !toml_path.is_file() {
+            return Err(Error::TomlNotFound(toml_path.to_owned()));
+        }
+    }
+    let started = Instant::now();
+    let lib = run::start(options)?;
+    let stepwise = options.stepwise || options.progress.is_some();
+    // Julia would turn Ctrl-C into a failure of the current model, and go on with the next
+    let interrupted = if stepwise || toml_paths.len() > 1 {
+        Some(run::handle_interrupts(stepwise)?)
+    } else {
+        None
     };
-    unsafe {
-        // Load the library
-        let lib = Library::new(shared_lib_path).unwrap();
+    let startup = started.elapsed();
 
-        // Init Julia
-        let init_julia: Symbol<unsafe extern "C" fn(i32, *const libc::c_char) -> i32> =
-            lib.get(b"init_julia").unwrap();
-        init_julia(0, CString::default().as_ptr());
+    let mut statuses = Vec::new();
+    for toml_path in toml_paths {
+        let started = Instant::now();
+        let status = run_model(
+            &lib,
+            toml_path,
+            options,
+            interrupted.as_deref().filter(|_| stepwise),
+        )
+        .unwrap_or_else(|err| {
+            eprintln!("error: {err}");
+            err.status()
+        });
+        statuses.push((status, started.elapsed()));
+        if interrupted
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::SeqCst))
+        {
+            break;
+        }
+    }
 
-        // Execute
-        let execute: Symbol<unsafe extern "C" fn(*const libc::c_char) -> i32> =
-            lib.get(b"execute").unwrap();
-        let toml_path_c = CString::new(cli.toml_path.to_str().unwrap()).unwrap();
-        let exit_code = execute(toml_path_c.as_ptr());
+    if toml_paths.len() > 1 {
+        let rows: Vec<Vec<String>> = toml_paths
+            .iter()
+            .enumerate()
+            .map(|(i, toml_path)| {
+                let (status, duration) = statuses.get(i).map_or_else(
+                    || ("not run".to_string(), String::new()),
+                    |(status, duration)| {
+                        (status.to_string(), table::duration(duration.as_secs_f64()))
+                    },
+                );
+                vec![toml_path.display().to_string(), status, duration]
+            })
+            .collect();
+        println!();
+        table::print(&["model", "status", "duration"], &rows);
+        println!();
+        let failed = statuses
+            .iter()
+            .filter(|(status, _)| *status != Status::Success)
+            .count();
+        println!(
+            "{} of {} models succeeded, Julia started in {}",
+            statuses.len() - failed,
+            toml_paths.len(),
+            table::duration(startup.as_secs_f64())
+        );
+    }
+    if statuses.len() < toml_paths.len() {
+        return Ok(Status::Interrupted);
+    }
+    Ok(statuses
+        .iter()
+        .map(|(status, _)| *status)
+        .find(|status| *status != Status::Success)
+        .unwrap_or(Status::Success))
+}
+
+fn run_model(
+    lib: &LibRibasim,
+    toml_path: &Path,
+    options: &RunOptions,
+    interrupted: Option<&AtomicBool>,
+) -> Result<Status, Error> {
+    let status = match interrupted {
+        Some(interrupted) => run::stepwise(lib, toml_path, options, interrupted)?,
+        None => run::execute(lib, toml_path, options)?,
+    };
+    if status != Status::Success
+        || (options.max_relative_error.is_none() && options.max_balance_error.is_none())
+    {
+        return Ok(status);
+    }
 
-        // Return with same exit code as `execute` did
-        ExitCode::from(exit_code as u8)
+    let config = load_config(toml_path, &options.overrides.set)?;
+    let violations = summary::balance_violations(
+        &config,
+        options.max_relative_error,
+        options.max_balance_error,
+    )?;
+    if violations.is_empty() {
+        return Ok(Status::Success);
     }
+    eprintln!(
+        "error: the water balance error of {} basin time step(s) exceeds the limit",
+        violations.len()
+    );
+    summary::print_violations(&violations);
+    Ok(Status::BalanceError)
 }
@@ -0,0 +1,707 @@
+//! Migration of models that were written for an older version of Ribasim.
+//!
+//! Each release that renamed tables or columns, or added columns that the Julia core
+//! requires, has a step in [`MIGRATIONS`]. A model is migrated by applying the steps of
+//! the versions after its `ribasim_version` in order, and then setting its
+//! `ribasim_version` to that of the CLI. A change is only made where it applies, so a
+//! model with an inaccurate `ribasim_version` is left intact.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arrow::{
+    array::{new_null_array, Array, ArrayRef, AsArray, RecordBatch, StringArray},
+    compute::cast,
+    datatypes::{DataType, Field, Schema as ArrowSchema, TimeUnit},
+    error::ArrowError,
+};
+use ribasim::{
+    config::{read_table, section, ConfigError, NODE_TABLES},
+    error::Error,
+    geopackage::{quote, Database},
+    results::{self, Table},
+    schema::ColumnType,
+};
+use toml_edit::DocumentMut;
+
+use crate::tables;
+
+/// The value of a column that is added to the existing rows.
+pub enum Fill {
+    Null,
+    /// A copy of another column.
+    Column(&'static str),
+}
+
+/// A change to the input of a model. Tables are named as in the database, like `Node` or
+/// `Basin / static`, and are changed in their Arrow file if the TOML refers to one.
+pub enum Change {
+    /// Rename a node type in the names of its tables, its TOML section and the node type
+    /// columns of all tables.
+    RenameNodeType {
+        from: &'static str,
+        to: &'static str,
+    },
+    RenameColumn {
+        table: &'static str,
+        from: &'static str,
+        to: &'static str,
+    },
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        column_type: ColumnType,
+        fill: Fill,
+    },
+}
+
+/// The changes that bring a model to the layout of a Ribasim version.
+pub struct Migration {
+    /// The first version with the new layout.
+    pub version: &'static str,
+    pub description: &'static str,
+    pub changes: &'static [Change],
+}
+
+pub const MIGRATIONS: [Migration; 3] = [
+    Migration {
+        version: "2024.4.0",
+        description: "The Node table has a node_id column, instead of using the fid",
+        changes: &[Change::AddColumn {
+            table: "Node",
+            column: "node_id",
+            column_type: ColumnType::Int32,
+            fill: Fill::Column("fid"),
+        }],
+    },
+    Migration {
+        version: "2024.5.0",
+        description: "The User node type is renamed to UserDemand",
+        changes: &[Change::RenameNodeType {
+            from: "User",
+            to: "UserDemand",
+        }],
+    },
+    Migration {
+        version: "2024.6.0",
+        description: "allocation_network_id is renamed to subnetwork_id",
+        // Models without allocation may have neither column
+        changes: &[
+            Change::RenameColumn {
+                table: "Node",
+                from: "allocation_network_id",
+                to: "subnetwork_id",
+            },
+            Change::RenameColumn {
+                table: "Edge",
+                from: "allocation_network_id",
+                to: "subnetwork_id",
+            },
+            Change::AddColumn {
+                table: "Node",
+                column: "subnetwork_id",
+                column_type: ColumnType::Int32,
+                fill: Fill::Null,
+            },
+            Change::AddColumn {
+                table: "Edge",
+                column: "subnetwork_id",
+                column_type: ColumnType::Int32,
+                fill: Fill::Null,
+            },
+        ],
+    },
+];
+
+/// What a migration changed, or would change in a dry run.
+pub struct MigrationReport {
+    /// The `ribasim_version` of the model before the migration.
+    pub from: String,
+    pub to: String,
+    /// The steps that apply to the model, with their changes as the lines of a diff.
+    pub steps: Vec<(&'static Migration, Vec<String>)>,
+    /// The removed and added lines of the TOML.
+    pub toml_diff: Vec<String>,
+    /// The copies of the original files, none in a dry run.
+    pub backups: Vec<PathBuf>,
+}
+
+/// Migrate a model to the version of the CLI. The database, the Arrow files and the TOML
+/// are only written if all steps succeed and `dry_run` is not set, after copying the
+/// originals to a `.bak` file next to them.
+pub fn migrate(toml_path: &Path, dry_run: bool) -> Result<MigrationReport, Error> {
+    let table = read_table(toml_path)?;
+    let setting = |key: &str| table.get(key).and_then(|value| value.as_str());
+    let from = setting("ribasim_version")
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!("{} has no ribasim_version", toml_path.display()))
+        })?
+        .to_string();
+    let to = env!("CARGO_PKG_VERSION").to_string();
+    let from_version = parse_version(&from).ok_or_else(|| {
+        Error::InvalidArgument(format!(
+            "{}: cannot read ribasim_version {from:?}",
+            toml_path.display()
+        ))
+    })?;
+    let to_version = parse_version(&to).expect("the package version is valid");
+    let mut report = MigrationReport {
+        from,
+        to,
+        steps: Vec::new(),
+        toml_diff: Vec::new(),
+        backups: Vec::new(),
+    };
+    if from_version > to_version {
+        return Err(Error::InvalidArgument(format!(
+            "{} is for Ribasim {}, which is newer than this Ribasim {}",
+            toml_path.display(),
+            report.from,
+            report.to
+        )));
+    } else if from_version == to_version {
+        return Ok(report);
+    }
+
+    let text =
+        fs::read_to_string(toml_path).map_err(|err| ConfigError::Io(toml_path.to_owned(), err))?;
+    let document: DocumentMut = text.parse().map_err(|err| {
+        Error::InvalidArgument(format!("Failed to parse {}: {err}", toml_path.display()))
+    })?;
+    let input_dir = toml_path
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(setting("input_dir").unwrap_or("."));
+    let database_path = input_dir.join(setting("database").unwrap_or("database.gpkg"));
+
+    if !dry_run {
+        // Fail before changing anything if an earlier backup is in the way
+        backup_path(toml_path)?;
+        let backup = backup_path(&database_path)?;
+        copy(&database_path, &backup)?;
+        report.backups.push(backup);
+    }
+    // Until a file is written, a failure removes the backups that were made
+    let discard = |report: &MigrationReport, err: Error| {
+        for backup in &report.backups {
+            let _ = fs::remove_file(backup);
+        }
+        err
+    };
+
+    let database = Database::open_writable(&database_path).map_err(|err| discard(&report, err))?;
+    // Dropping the transaction without committing it rolls back the changes
+    let transaction = database
+        .connection()
+        .unchecked_transaction()
+        .map_err(|source| discard(&report, database.error(source)))?;
+    let mut model = Model {
+        database: &database,
+        document,
+        input_dir,
+        arrow: BTreeMap::new(),
+    };
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| parse_version(migration.version) > Some(from_version.clone()))
+    {
+        let mut lines = Vec::new();
+        for change in migration.changes {
+            lines.extend(model.apply(change).map_err(|err| discard(&report, err))?);
+        }
+        report.steps.push((migration, lines));
+    }
+    // Keep the comment after the version
+    let version = model.document["ribasim_version"]
+        .as_value_mut()
+        .expect("the version was read from the TOML");
+    let decor = version.decor().clone();
+    *version = report.to.as_str().into();
+    *version.decor_mut() = decor;
+    let migrated = model.document.to_string();
+    report.toml_diff = diff(&text, &migrated);
+    if dry_run {
+        return Ok(report);
+    }
+
+    // Back up the TOML and Arrow files before writing any of them
+    let backup = backup_path(toml_path)
+        .and_then(|backup| copy(toml_path, &backup).map(|()| backup))
+        .map_err(|err| discard(&report, err))?;
+    report.backups.push(backup);
+    let mut rewrites = Vec::new();
+    for (path, (batch, changed)) in &model.arrow {
+        if !changed {
+            continue;
+        }
+        let compression = tables::compression(path)
+            .map_err(|source| arrow_error(path, ArrowError::ExternalError(source)))
+            .map_err(|err| discard(&report, err))?;
+        let backup = backup_path(path)
+            .and_then(|backup| copy(path, &backup).map(|()| backup))
+            .map_err(|err| discard(&report, err))?;
+        report.backups.push(backup);
+        rewrites.push((path, batch, compression));
+    }
+    // Write the Arrow files next to the originals, keeping their compression, and
+    // only move them in place once the database changes are committed
+    let mut staged = Vec::new();
+    for (path, batch, compression) in rewrites {
+        let write_error = |source| {
+            discard(
+                &report,
+                Error::Write {
+                    path: path.clone(),
+                    source,
+                },
+            )
+        };
+        let file = tempfile::Builder::new()
+            .suffix(".arrow")
+            .tempfile_in(path.parent().unwrap_or(Path::new("")))
+            .map_err(|err| write_error(err.into()))?;
+        results::write(batch, compression.codec(), file.path()).map_err(write_error)?;
+        staged.push((path, file));
+    }
+    transaction
+        .commit()
+        .map_err(|source| discard(&report, database.error(source)))?;
+    for (path, file) in staged {
+        file.persist(path).map_err(|err| Error::Write {
+            path: path.clone(),
+            source: err.error.into(),
+        })?;
+    }
+    fs::write(toml_path, migrated).map_err(|err| Error::Write {
+        path: toml_path.to_owned(),
+        source: err.into(),
+    })?;
+    Ok(report)
+}
+
+/// The numbers of a version like `2024.9.0`, ignoring a suffix like `-dev`.
+fn parse_version(version: &str) -> Option<Vec<u32>> {
+    let numbers: Vec<u32> = version
+        .split('.')
+        .map_while(|part| {
+            let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse().ok()
+        })
+        .collect();
+    (!numbers.is_empty()).then_some(numbers)
+}
+
+/// The path to keep the original of a file at, which must not exist yet.
+fn backup_path(path: &Path) -> Result<PathBuf, Error> {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".bak");
+    let backup = path.with_file_name(name);
+    if backup.exists() {
+        return Err(Error::InvalidArgument(format!(
+            "{} already exists, move it away to migrate again",
+            backup.display()
+        )));
+    }
+    Ok(backup)
+}
+
+fn copy(from: &Path, to: &Path) -> Result<(), Error> {
+    fs::copy(from, to).map(|_| ()).map_err(|err| Error::Write {
+        path: to.to_owned(),
+        source: err.into(),
+    })
+}
+
+/// Where a table is stored.
+enum Location {
+    Database(String),
+    Arrow(PathBuf),
+}
+
+/// The state of a model during a migration.
+struct Model<'a> {
+    database: &'a Database,
+    document: DocumentMut,
+    input_dir: PathBuf,
+    /// The Arrow files that were read, and whether they were changed.
+    arrow: BTreeMap<PathBuf, (RecordBatch, bool)>,
+}
+
+impl Model<'_> {
+    /// Apply a change, returning the lines of a diff that describe it.
+    fn apply(&mut self, change: &Change) -> Result<Vec<String>, Error> {
+        match *change {
+            Change::RenameNodeType { from, to } => self.rename_node_type(from, to),
+            Change::RenameColumn { table, from, to } => self.rename_column(table, from, to),
+            Change::AddColumn {
+                table,
+                column,
+                column_type,
+                ref fill,
+            } => self.add_column(table, column, column_type, fill),
+        }
+    }
+
+    fn rename_node_type(&mut self, from: &str, to: &str) -> Result<Vec<String>, Error> {
+        let mut lines = Vec::new();
+        let prefix = format!("{from} / ");
+        for name in self.database.tables()? {
+            if let Some(kind) = name.strip_prefix(&prefix) {
+                let new_name = format!("{to} / {kind}");
+                self.rename_table(&name, &new_name)?;
+                lines.push(format!("-table {name}"));
+                lines.push(format!("+table {new_name}"));
+            }
+        }
+        if let Some(item) = self.document.remove(&section(from)) {
+            // The table keeps its position in the file
+            self.document.insert(&section(to), item);
+        }
+
+        for name in self.database.tables()? {
+            for (column, _) in self.database.columns(&name)? {
+                if !is_node_type(&column) {
+                    continue;
+                }
+                let sql = format!(
+                    "UPDATE {table} SET {column} = ?2 WHERE {column} = ?1",
+                    table = quote(&name),
+                    column = quote(&column)
+                );
+                let rows = self.execute(&sql, [from, to])?;
+                if rows > 0 {
+                    lines.push(format!("-{name}: {column} {from} in {rows} row(s)"));
+                    lines.push(format!("+{name}: {column} {to}"));
+                }
+            }
+        }
+        for path in self.arrow_paths() {
+            let batch = self.batch(&path)?;
+            let mut columns = batch.columns().to_vec();
+            let mut replaced = Vec::new();
+            for (i, field) in batch.schema().fields().iter().enumerate() {
+                if !is_node_type(field.name()) {
+                    continue;
+                }
+                if let Some((array, rows)) =
+                    replace(&columns[i], from, to).map_err(|err| arrow_error(&path, err))?
+                {
+                    columns[i] = array;
+                    replaced.push((field.name().clone(), rows));
+                }
+            }
+            if replaced.is_empty() {
+                continue;
+            }
+            let batch = RecordBatch::try_new(batch.schema(), columns)
+                .map_err(|err| arrow_error(&path, err))?;
+            self.arrow.insert(path.clone(), (batch, true));
+            for (column, rows) in replaced {
+                let path = path.display();
+                lines.push(format!("-{path}: {column} {from} in {rows} row(s)"));
+                lines.push(format!("+{path}: {column} {to}"));
+            }
+        }
+        Ok(lines)
+    }
+
+    fn rename_column(&mut self, table: &str, from: &str, to: &str) -> Result<Vec<String>, Error> {
+        let mut lines = Vec::new();
+        match self.locate(table)? {
+            Some(Location::Database(name)) => {
+                let columns = self.database.columns(&name)?;
+                let has = |column: &str| columns.iter().any(|(name, _)| name == column);
+                if has(from) && !has(to) {
+                    let sql = format!(
+                        "ALTER TABLE {} RENAME COLUMN {} TO {}",
+                        quote(&name),
+                        quote(from),
+                        quote(to)
+                    );
+                    self.execute(&sql, [])?;
+                    lines.push(format!("-{name}: column {from}"));
+                    lines.push(format!("+{name}: column {to}"));
+                }
+            }
+            Some(Location::Arrow(path)) => {
+                let batch = self.batch(&path)?;
+                let schema = batch.schema();
+                if schema.column_with_name(from).is_some() && schema.column_with_name(to).is_none()
+                {
+                    let fields: Vec<Field> = schema
+                        .fields()
+                        .iter()
+                        .map(|field| {
+                            let field = field.as_ref().clone();
+                            if field.name() == from {
+                                field.with_name(to)
+                            } else {
+                                field
+                            }
+                        })
+                        .collect();
+                    let batch = with_fields(&batch, fields, batch.columns().to_vec())
+                        .map_err(|err| arrow_error(&path, err))?;
+                    self.arrow.insert(path.clone(), (batch, true));
+                    lines.push(format!("-{}: column {from}", path.display()));
+                    lines.push(format!("+{}: column {to}", path.display()));
+                }
+            }
+            None => {}
+        }
+        Ok(lines)
+    }
+
+    fn add_column(
+        &mut self,
+        table: &str,
+        column: &str,
+        column_type: ColumnType,
+        fill: &Fill,
+    ) -> Result<Vec<String>, Error> {
+        let fill_text = match fill {
+            Fill::Null => "NULL",
+            Fill::Column(source) => source,
+        };
+        let missing = |table: String, source: &str| Error::InvalidTable {
+            table,
+            message: format!("column {source} is missing"),
+        };
+        let mut lines = Vec::new();
+        match self.locate(table)? {
+            Some(Location::Database(name)) => {
+                let columns = self.database.columns(&name)?;
+                let has = |column: &str| columns.iter().any(|(name, _)| name == column);
+                if has(column) {
+                    return Ok(lines);
+                }
+                if let Fill::Column(source) = fill {
+                    if !has(source) {
+                        return Err(missing(name, source));
+                    }
+                }
+                let sql_type = tables::sql_type(&arrow_type(column_type))
+                    .expect("every column type has an SQLite type");
+                let sql = format!(
+                    "ALTER TABLE {} ADD COLUMN {} {sql_type}",
+                    quote(&name),
+                    quote(column)
+                );
+                self.execute(&sql, [])?;
+                if let Fill::Column(source) = fill {
+                    let sql = format!(
+                        "UPDATE {} SET {} = {}",
+                        quote(&name),
+                        quote(column),
+                        quote(source)
+                    );
+                    self.execute(&sql, [])?;
+                }
+                lines.push(format!("+{name}: column {column} = {fill_text}"));
+            }
+            Some(Location::Arrow(path)) => {
+                let batch = self.batch(&path)?;
+                if batch.schema().column_with_name(column).is_some() {
+                    return Ok(lines);
+                }
+                let data_type = arrow_type(column_type);
+                let array = match fill {
+                    Fill::Null => new_null_array(&data_type, batch.num_rows()),
+                    Fill::Column(source) => {
+                        let array = batch
+                            .column_by_name(source)
+                            .ok_or_else(|| missing(path.display().to_string(), source))?;
+                        cast(array, &data_type).map_err(|err| arrow_error(&path, err))?
+                    }
+                };
+                let mut fields: Vec<Field> = batch
+                    .schema()
+                    .fields()
+                    .iter()
+                    .map(|field| field.as_ref().clone())
+                    .collect();
+                fields.push(Field::new(column, data_type, true));
+                let mut columns = batch.columns().to_vec();
+                columns.push(array);
+                let batch =
+                    with_fields(&batch, fields, columns).map_err(|err| arrow_error(&path, err))?;
+                self.arrow.insert(path.clone(), (batch, true));
+                lines.push(format!(
+                    "+{}: column {column} = {fill_text}",
+                    path.display()
+                ));
+            }
+            None => {}
+        }
+        Ok(lines)
+    }
+
+    /// Where a table is stored, if it exists.
+    fn locate(&self, table: &str) -> Result<Option<Location>, Error> {
+        if let Some((node_type, kind)) = table.split_once(" / ") {
+            let path = self
+                .document
+                .get(&section(node_type))
+                .and_then(|section| section.get(kind))
+                .and_then(|path| path.as_str());
+            if let Some(path) = path {
+                return Ok(Some(Location::Arrow(self.input_dir.join(path))));
+            }
+        }
+        Ok(self
+            .database
+            .has_table(table)?
+            .then(|| Location::Database(table.to_string())))
+    }
+
+    /// The Arrow files that the TOML refers to and that exist.
+    fn arrow_paths(&self) -> Vec<PathBuf> {
+        NODE_TABLES
+            .iter()
+            .flat_map(|(section, kinds)| kinds.iter().map(move |kind| (*section, *kind)))
+            .filter_map(|(section, kind)| {
+                self.document
+                    .get(section)
+                    .and_then(|section| section.get(kind))
+                    .and_then(|path| path.as_str())
+                    .map(|path| self.input_dir.join(path))
+            })
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    /// The contents of an Arrow file, as changed by earlier steps.
+    fn batch(&mut self, path: &Path) -> Result<RecordBatch, Error> {
+        if let Some((batch, _)) = self.arrow.get(path) {
+            return Ok(batch.clone());
+        }
+        let batch = Table::read(path)?.batch;
+        self.arrow.insert(path.to_owned(), (batch.clone(), false));
+        Ok(batch)
+    }
+
+    /// Rename a table, and the references to it in the GeoPackage bookkeeping tables.
+    fn rename_table(&self, from: &str, to: &str) -> Result<(), Error> {
+        self.execute(
+            &format!("ALTER TABLE {} RENAME TO {}", quote(from), quote(to)),
+            [],
+        )?;
+        for table in [
+            "gpkg_contents",
+            "gpkg_geometry_columns",
+            "gpkg_data_columns",
+            "gpkg_extensions",
+        ] {
+            if self.database.has_table(table)? {
+                let sql = format!("UPDATE {table} SET table_name = ?2 WHERE table_name = ?1");
+                self.execute(&sql, [from, to])?;
+            }
+        }
+        if self.database.has_table("gpkg_contents")? {
+            self.execute(
+                "UPDATE gpkg_contents SET identifier = ?2 WHERE identifier = ?1",
+                [from, to],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn execute(&self, sql: &str, params: impl rusqlite::Params) -> Result<usize, Error> {
+        self.database
+            .connection()
+            .execute(sql, params)
+            .map_err(|source| self.database.error(source))
+    }
+}
+
+/// Whether a column holds node types, like `node_type` or `listen_node_type`.
+fn is_node_type(column: &str) -> bool {
+    column == "node_type" || column.ends_with("_node_type")
+}
+
+/// The Arrow type that the Julia core reads a column type from.
+fn arrow_type(column_type: ColumnType) -> DataType {
+    match column_type {
+        ColumnType::Int32 => DataType::Int32,
+        ColumnType::Int64 => DataType::Int64,
+        ColumnType::Float64 => DataType::Float64,
+        ColumnType::Bool => DataType::Boolean,
+        ColumnType::String => DataType::Utf8,
+        ColumnType::DateTime => DataType::Timestamp(TimeUnit::Millisecond, None),
+    }
+}
+
+/// Replace a text value in a column, returning the new column and the number of
+/// replaced values if there were any.
+fn replace(
+    array: &ArrayRef,
+    from: &str,
+    to: &str,
+) -> Result<Option<(ArrayRef, usize)>, ArrowError> {
+    let text = cast(array, &DataType::Utf8)?;
+    let text = text.as_string::<i32>();
+    let rows = text.iter().filter(|value| *value == Some(from)).count();
+    if rows == 0 {
+        return Ok(None);
+    }
+    let replaced: StringArray = text
+        .iter()
+        .map(|value| if value == Some(from) { Some(to) } else { value })
+        .collect();
+    let replaced = cast(&(Arc::new(replaced) as ArrayRef), array.data_type())?;
+    Ok(Some((replaced, rows)))
+}
+
+/// A batch with new fields and columns, keeping the schema metadata.
+fn with_fields(
+    batch: &RecordBatch,
+    fields: Vec<Field>,
+    columns: Vec<ArrayRef>,
+) -> Result<RecordBatch, ArrowError> {
+    let schema = ArrowSchema::new_with_metadata(fields, batch.schema().metadata().clone());
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+fn arrow_error(path: &Path, err: ArrowError) -> Error {
+    Error::InvalidTable {
+        table: path.display().to_string(),
+        message: err.to_string(),
+    }
+}
+
+/// The removed and added lines between two texts, without context.
+fn diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // The length of the longest common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(format!("-{}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+{}", new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
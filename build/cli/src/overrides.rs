@@ -0,0 +1,96 @@
+//! Command-line overrides of TOML keys, like `--set solver.dt=60`.
+//!
+//! This is the CLI counterpart of the keyword arguments of `config.Config(config_path; kwargs...)`.
+//! Since `execute` only accepts a path, the overridden configuration is written to a
+//! temporary TOML file that is passed on instead.
+
+use std::{
+    io::{self, Write},
+    path::{self, Path},
+};
+
+use tempfile::NamedTempFile;
+use toml::{Table, Value};
+
+use ribasim::config::{self, Config, ConfigError};
+
+/// Read a TOML file and apply the `section.key=value` assignments to it.
+///
+/// The result is checked against the configuration schema, so a misspelled key or a
+/// value of the wrong type is reported just like it would be in the file itself.
+pub fn load(path: &Path, assignments: &[String]) -> Result<(Config, Table), ConfigError> {
+    let mut table = config::read_table(path)?;
+    let errors: Vec<String> = assignments
+        .iter()
+        .filter_map(|assignment| apply(&mut table, assignment).err())
+        .collect();
+    if !errors.is_empty() {
+        return Err(ConfigError::Invalid(path.to_owned(), errors));
+    }
+    let config = Config::from_table(path, table.clone())?;
+    Ok((config, table))
+}
+
+/// Write the overridden TOML to a temporary file, which is removed when dropped.
+///
+/// The `input_dir` and `results_dir` are made absolute, such that they still resolve
+/// against the directory of the original TOML file.
+pub fn write_resolved(config: &Config, mut table: Table) -> io::Result<NamedTempFile> {
+    for key in ["input_dir", "results_dir"] {
+        if let Some(Value::String(dir)) = table.get(key) {
+            let dir = path::absolute(config.dir.join(dir))?;
+            table.insert(key.to_string(), Value::String(dir.display().to_string()));
+        }
+    }
+
+    let mut file = tempfile::Builder::new()
+        .prefix("ribasim-")
+        .suffix(".toml")
+        .tempfile()?;
+    let text = toml::to_string(&table).map_err(io::Error::other)?;
+    file.write_all(text.as_bytes())?;
+    file.flush()?;
+    Ok(file)
+}
+
+/// Apply a single `section.key=value` assignment.
+fn apply(table: &mut Table, assignment: &str) -> Result<(), String> {
+    let (key, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| format!("--set {assignment}: expected the form section.key=value"))?;
+    let key = key.trim();
+    let parts: Vec<&str> = key.split('.').collect();
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err(format!("--set {assignment}: invalid key {key:?}"));
+    }
+
+    let (name, sections) = parts.split_last().expect("split always yields a part");
+    let mut current = table;
+    for section in sections {
+        let entry = current
+            .entry(section.to_string())
+            .or_insert_with(|| Value::Table(Table::new()));
+        current = match entry {
+            Value::Table(table) => table,
+            _ => return Err(format!("--set {assignment}: {section} is not a section")),
+        };
+    }
+    let value = match parse_value(value.trim()) {
+        // Write `dt=60` as `dt = 60.0`, Julia does not convert it to a Float64 for us
+        Value::Integer(x) if config::FLOAT_KEYS.contains(&key) => Value::Float(x as f64),
+        value => value,
+    };
+    current.insert(name.to_string(), value);
+    Ok(())
+}
+
+/// Interpret a value as TOML, falling back to a plain string.
+///
+/// This way `solver.dt=60` gives a number, while `solver.algorithm=Tsit5` needs no quotes.
+fn parse_value(value: &str) -> Value {
+    format!("value = {value}")
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
@@ -0,0 +1,159 @@
+//! Progress reporting of a stepwise run, as a progress bar or as periodic records.
+
+use std::{
+    io::{self, IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use ribasim::config::datetime_since;
+use serde_json::json;
+
+use crate::table;
+
+/// How progress is reported on stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+    /// A progress bar that is redrawn in place, meant for a terminal
+    Bar,
+    /// A line of text per interval, meant for log files
+    Plain,
+    /// A JSON object per line per interval, meant for other programs
+    Json,
+}
+
+impl ProgressFormat {
+    /// A progress bar if stderr is a terminal, and no progress reporting otherwise.
+    pub fn detect() -> Option<ProgressFormat> {
+        io::stderr().is_terminal().then_some(ProgressFormat::Bar)
+    }
+}
+
+/// Minimal interval between redraws of the progress bar.
+const BAR_INTERVAL: Duration = Duration::from_millis(200);
+const BAR_WIDTH: usize = 30;
+
+/// Tracks the simulation time against the wall-clock time.
+pub struct Progress {
+    format: ProgressFormat,
+    interval: Duration,
+    starttime: NaiveDateTime,
+    start_time: f64,
+    end_time: f64,
+    started: Instant,
+    /// When the last report was made, and for which time.
+    reported: Option<(Instant, f64)>,
+}
+
+impl Progress {
+    /// Start tracking, with times in seconds since `starttime` as used by the BMI.
+    ///
+    /// The `interval` applies to the records of the plain and JSON formats.
+    pub fn new(
+        format: ProgressFormat,
+        interval: Duration,
+        starttime: NaiveDateTime,
+        start_time: f64,
+        end_time: f64,
+    ) -> Progress {
+        Progress {
+            format,
+            interval,
+            starttime,
+            start_time,
+            end_time,
+            started: Instant::now(),
+            reported: None,
+        }
+    }
+
+    /// Report the current time if the interval has passed since the last report.
+    pub fn update(&mut self, time: f64) {
+        let interval = match self.format {
+            ProgressFormat::Bar => BAR_INTERVAL,
+            ProgressFormat::Plain | ProgressFormat::Json => self.interval,
+        };
+        if self
+            .reported
+            .is_some_and(|(reported, _)| reported.elapsed() < interval)
+        {
+            return;
+        }
+        self.report(time);
+    }
+
+    /// Report the time at which the simulation ended, regardless of the interval.
+    pub fn finish(&mut self, time: f64) {
+        if self.reported.is_none_or(|(_, reported)| reported != time) {
+            self.report(time);
+        }
+        if self.format == ProgressFormat::Bar {
+            eprintln!();
+        }
+    }
+
+    fn report(&mut self, time: f64) {
+        self.reported = Some((Instant::now(), time));
+        let duration = self.end_time - self.start_time;
+        let simulated = time - self.start_time;
+        let fraction = if duration > 0.0 {
+            (simulated / duration).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let elapsed = self.started.elapsed().as_secs_f64();
+        // Model seconds per wall-clock second
+        let rate = (elapsed > 0.0).then(|| simulated / elapsed);
+        let eta = rate
+            .filter(|&rate| rate > 0.0)
+            .map(|rate| (self.end_time - time).max(0.0) / rate);
+        let date = datetime_since(time, self.starttime);
+
+        match self.format {
+            ProgressFormat::Bar => {
+                let filled = (fraction * BAR_WIDTH as f64).round() as usize;
+                eprint!(
+                    "\r{date} [{}{}] {:5.1}%  {}  ETA {}  ",
+                    "#".repeat(filled),
+                    " ".repeat(BAR_WIDTH - filled),
+                    100.0 * fraction,
+                    format_rate(rate),
+                    format_eta(eta)
+                );
+                let _ = io::stderr().flush();
+            }
+            ProgressFormat::Plain => eprintln!(
+                "{date}  {:5.1}%  {}  elapsed {}  ETA {}",
+                100.0 * fraction,
+                format_rate(rate),
+                table::duration(elapsed),
+                format_eta(eta)
+            ),
+            ProgressFormat::Json => eprintln!(
+                "{}",
+                json!({
+                    "model_time": date.format("%Y-%m-%dT%H:%M:%S").to_string(),
+                    "time": time,
+                    "end_time": self.end_time,
+                    "fraction": fraction,
+                    "elapsed": elapsed,
+                    "rate": rate,
+                    "eta": eta,
+                })
+            ),
+        }
+    }
+}
+
+/// Format a rate in model days per wall-clock second.
+fn format_rate(rate: Option<f64>) -> String {
+    match rate {
+        Some(rate) => format!("{:.1} days/s", rate / 86400.0),
+        None => "? days/s".to_string(),
+    }
+}
+
+fn format_eta(eta: Option<f64>) -> String {
+    eta.map_or_else(|| "?".to_string(), table::duration)
+}
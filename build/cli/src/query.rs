@@ -0,0 +1,200 @@
+//! Time series of single nodes or edges from the results.
+
+use std::io;
+
+use arrow::{
+    array::{Array, AsArray, BooleanArray, RecordBatch},
+    compute::filter_record_batch,
+    datatypes::Float64Type,
+    util::display::{ArrayFormatter, FormatOptions},
+};
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use ribasim::{
+    config::Config,
+    error::Error,
+    results::{key_columns, Table, RESULTS_FILES},
+};
+
+use crate::table;
+
+/// How the queried rows are printed on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum QueryFormat {
+    /// Aligned columns for reading
+    Table,
+    /// Comma separated values
+    Csv,
+    /// A JSON array of objects
+    Json,
+}
+
+/// Which rows and columns of a results table to print.
+pub struct Query<'a> {
+    pub table: &'a str,
+    /// Node IDs, matching either end of an edge for the flow tables.
+    pub nodes: &'a [i32],
+    /// Node type, matching the same end of an edge as the node IDs for the flow tables.
+    pub node_type: Option<&'a str>,
+    pub edges: &'a [i32],
+    pub subgrids: &'a [i32],
+    /// Value columns to print, all by default.
+    pub vars: &'a [String],
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+}
+
+/// Read the rows of a results table that match the query.
+///
+/// Only the key columns and the requested value columns are read from the file.
+pub fn query(config: &Config, query: &Query) -> Result<RecordBatch, Error> {
+    let file = RESULTS_FILES
+        .iter()
+        .find(|(name, _)| *name == query.table)
+        .map(|(_, file)| *file)
+        .expect("clap only accepts known tables");
+    let path = config.results_path(file);
+    let keys = key_columns(query.table);
+
+    let check = |filter: &str, given: bool, column: &str| {
+        if given && !keys.contains(&column) {
+            Err(Error::InvalidArgument(format!(
+                "{filter} cannot be used for the {} results",
+                query.table
+            )))
+        } else {
+            Ok(())
+        }
+    };
+    let (node_columns, type_columns) = match query.table {
+        "flow" | "allocation_flow" => (
+            vec!["from_node_id", "to_node_id"],
+            vec!["from_node_type", "to_node_type"],
+        ),
+        "control" => (vec!["control_node_id"], vec![]),
+        _ => (vec!["node_id"], vec!["node_type"]),
+    };
+    check("--node", !query.nodes.is_empty(), node_columns[0])?;
+    check(
+        "--node-type",
+        query.node_type.is_some(),
+        type_columns.first().copied().unwrap_or("node_type"),
+    )?;
+    check("--edge", !query.edges.is_empty(), "edge_id")?;
+    check("--subgrid", !query.subgrids.is_empty(), "subgrid_id")?;
+
+    let table = if query.vars.is_empty() {
+        Table::read(&path)?
+    } else {
+        let mut columns = keys.to_vec();
+        columns.extend(query.vars.iter().map(String::as_str));
+        Table::read_columns(&path, &columns)?
+    };
+
+    let time = table.times("time")?;
+    let mut mask: Vec<bool> = time
+        .iter()
+        .map(|time| {
+            query.from.is_none_or(|from| *time >= from) && query.to.is_none_or(|to| *time <= to)
+        })
+        .collect();
+    let mut restrict = |matches: Vec<bool>| {
+        for (keep, matches) in mask.iter_mut().zip(matches) {
+            *keep &= matches;
+        }
+    };
+
+    if !query.nodes.is_empty() || query.node_type.is_some() {
+        // A row matches if any end of the edge has the node ID and type
+        let mut matches = vec![false; table.num_rows()];
+        for (i, id_column) in node_columns.iter().enumerate() {
+            let ids = table.i32s(id_column)?;
+            let types = match (query.node_type, type_columns.get(i)) {
+                (Some(_), Some(column)) => Some(table.strings(column)?),
+                _ => None,
+            };
+            for (row, matched) in matches.iter_mut().enumerate() {
+                let id_matches =
+                    query.nodes.is_empty() || ids[row].is_some_and(|id| query.nodes.contains(&id));
+                let type_matches = match (&types, query.node_type) {
+                    (Some(types), Some(node_type)) => types[row].as_deref() == Some(node_type),
+                    _ => true,
+                };
+                *matched |= id_matches && type_matches;
+            }
+        }
+        restrict(matches);
+    }
+    for (ids, column) in [(query.edges, "edge_id"), (query.subgrids, "subgrid_id")] {
+        if !ids.is_empty() {
+            let values = table.i32s(column)?;
+            restrict(
+                values
+                    .iter()
+                    .map(|value| value.is_some_and(|value| ids.contains(&value)))
+                    .collect(),
+            );
+        }
+    }
+
+    filter_record_batch(&table.batch, &BooleanArray::from(mask)).map_err(|source| Error::Arrow {
+        path: table.path.clone(),
+        source,
+    })
+}
+
+/// Print the queried rows in the given format.
+pub fn print(batch: &RecordBatch, format: QueryFormat) -> Result<(), Error> {
+    let write_error = |source: arrow::error::ArrowError| Error::Write {
+        path: "stdout".into(),
+        source: source.into(),
+    };
+    match format {
+        QueryFormat::Csv => {
+            let mut writer = arrow::csv::WriterBuilder::new()
+                .with_timestamp_format("%Y-%m-%dT%H:%M:%S%.f".to_string())
+                .build(io::stdout().lock());
+            writer.write(batch).map_err(write_error)?;
+        }
+        QueryFormat::Json => {
+            let mut writer = arrow::json::ArrayWriter::new(io::stdout().lock());
+            writer.write(batch).map_err(write_error)?;
+            writer.finish().map_err(write_error)?;
+            println!();
+        }
+        QueryFormat::Table => {
+            let options = FormatOptions::default();
+            let schema = batch.schema();
+            let header: Vec<&str> = schema
+                .fields()
+                .iter()
+                .map(|field| field.name().as_str())
+                .collect();
+            let formatters = batch
+                .columns()
+                .iter()
+                .map(|column| ArrayFormatter::try_new(column, &options))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(write_error)?;
+            let rows: Vec<Vec<String>> = (0..batch.num_rows())
+                .map(|row| {
+                    batch
+                        .columns()
+                        .iter()
+                        .zip(&formatters)
+                        .map(
+                            |(column, formatter)| match column.as_primitive_opt::<Float64Type>() {
+                                Some(column) if !column.is_null(row) => {
+                                    table::number(column.value(row))
+                                }
+                                _ => formatter.value(row).to_string(),
+                            },
+                        )
+                        .collect()
+                })
+                .collect();
+            table::print(&header, &rows);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,397 @@
+//! An interactive shell over the BMI, to step through a model and inspect its state.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+};
+
+use arrow::{
+    array::AsArray,
+    compute::cast,
+    datatypes::{DataType, Int32Type},
+};
+use ribasim::{
+    bmi::Model,
+    config::{datetime_since, parse_datetime, Config},
+    error::{Error, Status},
+    geopackage::{quote, Database, Node},
+    library::LibRibasim,
+    results::Table,
+    schema::SCHEMAS,
+};
+
+use crate::{overrides, LibraryArgs};
+
+const HELP: &str = "\
+Commands:
+  step [N]                     take N internal time steps, 1 by default
+  until DATE|SECONDS           run until a date-time, or seconds since the start
+  get VAR [--node ID]          print the values of a variable, optionally for one node
+  shape VAR                    print the shape of a variable
+  vars                         list the variables
+  time                         print the current, start and end time
+  finalize                     write the results and exit
+  help                         print this help
+  quit                         exit, which also finalizes the model";
+
+/// The variables that libribasim exposes through `get_value_ptr`.
+const VARIABLES: [&str; 9] = [
+    "basin.storage",
+    "basin.level",
+    "basin.infiltration",
+    "basin.drainage",
+    "basin.infiltration_integrated",
+    "basin.drainage_integrated",
+    "basin.subgrid_level",
+    "user_demand.demand",
+    "user_demand.realized",
+];
+
+/// What a single element of a variable belongs to.
+struct Label {
+    node_id: Option<i32>,
+    text: String,
+}
+
+struct Shell<'lib> {
+    model: Model<'lib>,
+    config: Config,
+    /// Nodes per node type, sorted by node ID.
+    nodes: BTreeMap<String, Vec<Node>>,
+    /// Subgrid IDs in the order of `basin.subgrid_level`.
+    subgrid_ids: Vec<i32>,
+    /// Priorities in the order of the columns of `user_demand.demand`.
+    priorities: Vec<i32>,
+}
+
+/// Start a model and read commands from stdin until it is finalized.
+///
+/// When stdin is not a terminal, the commands are treated as a script that stops at the
+/// first error.
+pub fn repl(toml_path: &Path, set: &[String], library: &LibraryArgs) -> Result<Status, Error> {
+    if !toml_path.is_file() {
+        return Err(Error::TomlNotFound(toml_path.to_owned()));
+    }
+    let (config, table) = overrides::load(toml_path, set)?;
+    let resolved = if set.is_empty() {
+        None
+    } else {
+        Some(overrides::write_resolved(&config, table).map_err(Error::ResolvedToml)?)
+    };
+    let model_path = resolved.as_ref().map_or(toml_path, |file| file.path());
+
+    let db = Database::open(&config.database_path())?;
+    let mut nodes: BTreeMap<String, Vec<Node>> = BTreeMap::new();
+    for node in db.nodes()? {
+        nodes.entry(node.node_type.clone()).or_default().push(node);
+    }
+    let subgrid_ids = read_subgrid_ids(&db)?;
+    let priorities = read_priorities(&config, &db)?;
+
+    let lib = LibRibasim::load(library.lib.as_deref(), library.verbose)?;
+    if library.verbose {
+        eprintln!("Loaded {}", lib.path.display());
+    }
+    let model = Model::initialize(&lib, model_path)?;
+    let mut shell = Shell {
+        model,
+        config,
+        nodes,
+        subgrid_ids,
+        priorities,
+    };
+
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        println!("Type `help` for a list of commands.");
+    }
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        if interactive {
+            print!("ribasim> ");
+            let _ = io::stdout().flush();
+        }
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line.map_err(|err| Error::Repl(err.to_string()))?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            continue;
+        };
+        let result = match command {
+            "finalize" | "quit" | "exit" => break,
+            "help" => {
+                println!("{HELP}");
+                Ok(())
+            }
+            "vars" => {
+                VARIABLES.iter().for_each(|name| println!("{name}"));
+                Ok(())
+            }
+            "step" => shell.step(args),
+            "until" => shell.until(args),
+            "get" => shell.get(args),
+            "shape" => shell.shape(args),
+            "time" => shell.time(),
+            _ => Err(Error::Repl(format!(
+                "Unknown command `{command}`, type `help` for a list of commands"
+            ))),
+        };
+        match result {
+            Ok(()) => {}
+            Err(err) if interactive => eprintln!("error: {err}"),
+            Err(err) => return Err(err),
+        }
+    }
+
+    let results_dir = shell.config.dir.join(&shell.config.results_dir);
+    shell.model.finalize()?;
+    println!(
+        "Finalized, results are written to {}",
+        results_dir.display()
+    );
+    Ok(Status::Success)
+}
+
+impl Shell<'_> {
+    fn step(&mut self, args: &[&str]) -> Result<(), Error> {
+        let n = match args {
+            [] => 1,
+            [n] => n
+                .parse::<usize>()
+                .map_err(|_| Error::Repl(format!("Invalid number of steps `{n}`")))?,
+            _ => return Err(Error::Repl("Usage: step [N]".to_string())),
+        };
+        for _ in 0..n {
+            self.model.update()?;
+        }
+        self.time()
+    }
+
+    fn until(&mut self, args: &[&str]) -> Result<(), Error> {
+        if args.is_empty() {
+            return Err(Error::Repl("Usage: until DATE|SECONDS".to_string()));
+        }
+        let arg = args.join(" ");
+        let time = match arg.parse::<f64>() {
+            Ok(seconds) => seconds,
+            Err(_) => {
+                let datetime = parse_datetime(&arg).ok_or_else(|| {
+                    Error::Repl(format!("Invalid date-time or number of seconds `{arg}`"))
+                })?;
+                (datetime - self.config.starttime).num_milliseconds() as f64 / 1000.0
+            }
+        };
+        let end_time = self.model.end_time()?;
+        if time > end_time {
+            return Err(Error::Repl(format!(
+                "{arg} is after the end time {}",
+                self.config.endtime
+            )));
+        }
+        if time < self.model.current_time()? {
+            return Err(Error::Repl(format!(
+                "{arg} is before the current time, the model cannot go back"
+            )));
+        }
+        self.model.update_until(time)?;
+        self.time()
+    }
+
+    fn get(&mut self, args: &[&str]) -> Result<(), Error> {
+        let (name, node_id) = match args {
+            [name] => (*name, None),
+            [name, "--node", id] | ["--node", id, name] => {
+                let id = id
+                    .parse::<i32>()
+                    .map_err(|_| Error::Repl(format!("Invalid node ID `{id}`")))?;
+                (*name, Some(id))
+            }
+            _ => return Err(Error::Repl("Usage: get VAR [--node ID]".to_string())),
+        };
+        if name == "basin.subgrid_level" {
+            self.model.update_subgrid_level()?;
+        }
+        let values = self.model.value(name)?;
+        let labels = self.labels(name, values.len());
+
+        let rows: Vec<(&Label, f64)> = labels
+            .iter()
+            .zip(values.iter().copied())
+            .filter(|(label, _)| node_id.is_none() || label.node_id == node_id)
+            .collect();
+        if let (Some(node_id), true) = (node_id, rows.is_empty()) {
+            return Err(Error::Repl(format!(
+                "Node #{node_id} is not part of {name}"
+            )));
+        }
+        let width = rows.iter().map(|(label, _)| label.text.len()).max();
+        for (label, value) in rows {
+            println!(
+                "{:<width$}  {value:>16.6}",
+                label.text,
+                width = width.unwrap_or(0)
+            );
+        }
+        Ok(())
+    }
+
+    fn shape(&self, args: &[&str]) -> Result<(), Error> {
+        let [name] = args else {
+            return Err(Error::Repl("Usage: shape VAR".to_string()));
+        };
+        let shape = self.model.var_shape(name)?;
+        let n_users = self.nodes_of_type("UserDemand").len();
+        match (*name, shape.as_slice()) {
+            ("user_demand.demand", [len]) if n_users > 0 => println!(
+                "{shape:?}, {n_users} UserDemand nodes by {} priorities",
+                len / n_users
+            ),
+            _ => println!("{shape:?}"),
+        }
+        Ok(())
+    }
+
+    fn time(&self) -> Result<(), Error> {
+        let current = self.model.current_time()?;
+        let end = self.model.end_time()?;
+        println!(
+            "{} (t = {current} s), start {}, end {}",
+            datetime_since(current, self.config.starttime),
+            self.config.starttime,
+            datetime_since(end, self.config.starttime)
+        );
+        Ok(())
+    }
+
+    fn nodes_of_type(&self, node_type: &str) -> &[Node] {
+        self.nodes.get(node_type).map_or(&[], Vec::as_slice)
+    }
+
+    /// Label the elements of a variable with the node they belong to, falling back to
+    /// the index if the variable does not match the Node table.
+    fn labels(&self, name: &str, len: usize) -> Vec<Label> {
+        let node_label = |node: &Node| {
+            if node.name.is_empty() {
+                format!("{} #{}", node.node_type, node.node_id)
+            } else {
+                format!("{} #{} {}", node.node_type, node.node_id, node.name)
+            }
+        };
+
+        let labels: Vec<Label> = match name {
+            "basin.subgrid_level" => self
+                .subgrid_ids
+                .iter()
+                .map(|&id| Label {
+                    node_id: None,
+                    text: format!("subgrid #{id}"),
+                })
+                .collect(),
+            "user_demand.demand" => {
+                let users = self.nodes_of_type("UserDemand");
+                // The demand matrix of users by priorities is flattened column-major
+                self.priorities
+                    .iter()
+                    .flat_map(|priority| {
+                        users.iter().map(move |node| Label {
+                            node_id: Some(node.node_id),
+                            text: format!("{} priority {priority}", node_label(node)),
+                        })
+                    })
+                    .collect()
+            }
+            _ => {
+                let node_type = if name.starts_with("basin.") {
+                    "Basin"
+                } else {
+                    "UserDemand"
+                };
+                self.nodes_of_type(node_type)
+                    .iter()
+                    .map(|node| Label {
+                        node_id: Some(node.node_id),
+                        text: node_label(node),
+                    })
+                    .collect()
+            }
+        };
+
+        if labels.len() == len {
+            labels
+        } else {
+            (0..len)
+                .map(|i| Label {
+                    node_id: None,
+                    text: format!("[{}]", i + 1),
+                })
+                .collect()
+        }
+    }
+}
+
+/// The subgrid IDs in ascending order, if the subgrid table is in the database.
+fn read_subgrid_ids(db: &Database) -> Result<Vec<i32>, Error> {
+    if !db.has_table("Basin / subgrid")? {
+        return Ok(Vec::new());
+    }
+    let mut statement = db
+        .connection()
+        .prepare("SELECT DISTINCT subgrid_id FROM \"Basin / subgrid\" ORDER BY subgrid_id")
+        .map_err(|source| db.error(source))?;
+    let ids = statement
+        .query_map([], |row| row.get(0))
+        .and_then(|rows| rows.collect())
+        .map_err(|source| db.error(source))?;
+    Ok(ids)
+}
+
+/// The sorted unique priorities of all demand tables, like `allocation.priorities` in
+/// the core.
+fn read_priorities(config: &Config, db: &Database) -> Result<Vec<i32>, Error> {
+    let mut priorities = BTreeSet::new();
+    for schema in SCHEMAS
+        .iter()
+        .filter(|schema| schema.column("priority").is_some())
+    {
+        let path = config
+            .tables
+            .get(schema.section)
+            .and_then(|paths| paths.get(schema.kind));
+        match path {
+            Some(path) => {
+                let table = Table::read(&config.input_path(path))?;
+                let invalid = |message: &str| Error::InvalidTable {
+                    table: schema.table_name(),
+                    message: format!("{message} in {}", table.path.display()),
+                };
+                let array = table
+                    .batch
+                    .column_by_name("priority")
+                    .ok_or_else(|| invalid("column priority is missing"))?;
+                let array = cast(array, &DataType::Int32)
+                    .map_err(|_| invalid("column priority is not an integer"))?;
+                priorities.extend(array.as_primitive::<Int32Type>().iter().flatten());
+            }
+            None if db.has_table(&schema.table_name())? => {
+                let sql = format!(
+                    "SELECT DISTINCT priority FROM {}",
+                    quote(&schema.table_name())
+                );
+                let mut statement = db
+                    .connection()
+                    .prepare(&sql)
+                    .map_err(|source| db.error(source))?;
+                let rows: Vec<Option<i32>> = statement
+                    .query_map([], |row| row.get(0))
+                    .and_then(|rows| rows.collect())
+                    .map_err(|source| db.error(source))?;
+                priorities.extend(rows.into_iter().flatten());
+            }
+            None => {}
+        }
+    }
+    Ok(priorities.into_iter().collect())
+}
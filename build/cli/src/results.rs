@@ -0,0 +1,347 @@
+//! Reading the Arrow files that a simulation writes to the `results_dir`.
+
+use std::{
+    error::Error as StdError,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arrow::{
+    array::{Array, AsArray, PrimitiveArray, RecordBatch},
+    compute::concat_batches,
+    datatypes::{
+        ArrowPrimitiveType, DataType, Float64Type, Int32Type, SchemaRef, TimeUnit,
+        TimestampMicrosecondType, TimestampMillisecondType, TimestampNanosecondType,
+        TimestampSecondType,
+    },
+    ipc::{
+        reader::FileReader,
+        writer::{FileWriter, IpcWriteOptions},
+        CompressionType,
+    },
+    util::display::{ArrayFormatter, FormatOptions},
+};
+use chrono::{DateTime, NaiveDateTime};
+
+use crate::{config::Config, error::Error};
+
+/// The results files per table, the same as `RESULTS_FILENAME` in the Julia core.
+pub const RESULTS_FILES: [(&str, &str); 6] = [
+    ("basin", "basin.arrow"),
+    ("flow", "flow.arrow"),
+    ("control", "control.arrow"),
+    ("allocation", "allocation.arrow"),
+    ("allocation_flow", "allocation_flow.arrow"),
+    ("subgrid_level", "subgrid_level.arrow"),
+];
+
+/// The columns that identify a row of each results table, the other columns
+/// hold values.
+///
+/// The node columns of flow are determined by `edge_id`, but are part of the key since
+/// `edge_id` can be missing.
+pub const KEY_COLUMNS: [(&str, &[&str]); 6] = [
+    ("basin", &["time", "node_id"]),
+    (
+        "flow",
+        &[
+            "time",
+            "edge_id",
+            "from_node_type",
+            "from_node_id",
+            "to_node_type",
+            "to_node_id",
+        ],
+    ),
+    ("control", &["time", "control_node_id"]),
+    (
+        "allocation",
+        &["time", "subnetwork_id", "node_type", "node_id", "priority"],
+    ),
+    (
+        "allocation_flow",
+        &[
+            "time",
+            "edge_id",
+            "from_node_type",
+            "from_node_id",
+            "to_node_type",
+            "to_node_id",
+            "subnetwork_id",
+            "priority",
+            "optimization_type",
+        ],
+    ),
+    ("subgrid_level", &["time", "subgrid_id"]),
+];
+
+/// The key columns of a results table, see [`KEY_COLUMNS`].
+pub fn key_columns(name: &str) -> &'static [&'static str] {
+    KEY_COLUMNS
+        .iter()
+        .find(|(table, _)| *table == name)
+        .map_or(&[], |(_, keys)| keys)
+}
+
+/// Describe each row by its key columns, like "time=2020-01-01T00:00:00, node_id=1".
+pub fn row_keys(table: &Table, keys: &[&str]) -> Result<Vec<String>, Error> {
+    let options = FormatOptions::default().with_null("null");
+    let mut formatters = Vec::new();
+    for key in keys {
+        let column = table
+            .batch
+            .column_by_name(key)
+            .ok_or_else(|| Error::InvalidResults {
+                path: table.path.clone(),
+                message: format!("key column {key} is missing"),
+            })?;
+        let formatter =
+            ArrayFormatter::try_new(column, &options).map_err(|source| Error::Arrow {
+                path: table.path.clone(),
+                source,
+            })?;
+        formatters.push((key, formatter));
+    }
+    Ok((0..table.num_rows())
+        .map(|i| {
+            formatters
+                .iter()
+                .map(|(name, formatter)| format!("{name}={}", formatter.value(i)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .collect())
+}
+
+/// Read the schema of an Arrow IPC file, without reading its data.
+pub fn read_schema(path: &Path) -> Result<SchemaRef, Error> {
+    let arrow_error = |source| Error::Arrow {
+        path: path.to_owned(),
+        source,
+    };
+    let file = File::open(path).map_err(|err| arrow_error(err.into()))?;
+    let reader = FileReader::try_new(file, None).map_err(arrow_error)?;
+    Ok(reader.schema())
+}
+
+/// Write a record batch to an Arrow IPC file, creating its directory.
+///
+/// The arrow crate compresses zstd at its own default level, so a `compression_level`
+/// from the TOML cannot be passed on.
+pub fn write(
+    batch: &RecordBatch,
+    compression: Option<CompressionType>,
+    path: &Path,
+) -> Result<(), Box<dyn StdError + Send + Sync>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let options = IpcWriteOptions::default().try_with_compression(compression)?;
+    let mut writer =
+        FileWriter::try_new_with_options(File::create(path)?, &batch.schema(), options)?;
+    writer.write(batch)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// A results table, with all record batches of the file combined.
+pub struct Table {
+    pub path: PathBuf,
+    pub batch: RecordBatch,
+}
+
+impl Table {
+    /// Read an Arrow IPC file.
+    pub fn read(path: &Path) -> Result<Table, Error> {
+        Table::read_projected(path, None)
+    }
+
+    /// Read only the given columns of an Arrow IPC file, in the given order.
+    pub fn read_columns(path: &Path, columns: &[&str]) -> Result<Table, Error> {
+        Table::read_projected(path, Some(columns))
+    }
+
+    fn read_projected(path: &Path, columns: Option<&[&str]>) -> Result<Table, Error> {
+        let arrow_error = |source| Error::Arrow {
+            path: path.to_owned(),
+            source,
+        };
+        let file = File::open(path).map_err(|err| {
+            if err.kind() == std::io::ErrorKind::NotFound {
+                Error::ResultsNotFound(path.to_owned())
+            } else {
+                Error::Arrow {
+                    path: path.to_owned(),
+                    source: err.into(),
+                }
+            }
+        })?;
+        let mut reader = FileReader::try_new(file, None).map_err(arrow_error)?;
+        let mut schema = reader.schema();
+        if let Some(columns) = columns {
+            // The schema is in the footer, so only the projected columns are read
+            let projection = columns
+                .iter()
+                .map(|column| {
+                    schema.index_of(column).map_err(|_| Error::InvalidResults {
+                        path: path.to_owned(),
+                        message: format!("column {column} is missing"),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let file = File::open(path).map_err(|err| arrow_error(err.into()))?;
+            schema = Arc::new(schema.project(&projection).map_err(arrow_error)?);
+            reader = FileReader::try_new(file, Some(projection)).map_err(arrow_error)?;
+        }
+        let batches = reader.collect::<Result<Vec<_>, _>>().map_err(arrow_error)?;
+        let batch = concat_batches(&schema, &batches).map_err(arrow_error)?;
+        Ok(Table {
+            path: path.to_owned(),
+            batch,
+        })
+    }
+
+    /// Read a results table by name, like "basin", from the `results_dir` of a model.
+    pub fn read_results(config: &Config, name: &str) -> Result<Table, Error> {
+        let file = RESULTS_FILES
+            .iter()
+            .find(|(table, _)| *table == name)
+            .map_or_else(|| format!("{name}.arrow"), |(_, file)| file.to_string());
+        Table::read(&config.results_path(file))
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.batch.num_rows()
+    }
+
+    /// A value of the schema metadata, like "ribasim_version".
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        self.batch
+            .schema_ref()
+            .metadata()
+            .get(key)
+            .map(String::as_str)
+    }
+
+    /// A column of a primitive type, failing if it is missing or has another type.
+    pub fn primitive<T: ArrowPrimitiveType>(
+        &self,
+        name: &str,
+    ) -> Result<&PrimitiveArray<T>, Error> {
+        self.batch
+            .column_by_name(name)
+            .and_then(|column| column.as_primitive_opt::<T>())
+            .ok_or_else(|| self.missing_column(name, T::DATA_TYPE))
+    }
+
+    /// A Float64 column, with nulls read as NaN.
+    pub fn f64s(&self, name: &str) -> Result<Vec<f64>, Error> {
+        let column = self.primitive::<Float64Type>(name)?;
+        Ok((0..column.len())
+            .map(|i| {
+                if column.is_null(i) {
+                    f64::NAN
+                } else {
+                    column.value(i)
+                }
+            })
+            .collect())
+    }
+
+    /// An Int32 column, like a node ID.
+    pub fn i32s(&self, name: &str) -> Result<Vec<Option<i32>>, Error> {
+        Ok(self.primitive::<Int32Type>(name)?.iter().collect())
+    }
+
+    /// A String column.
+    pub fn strings(&self, name: &str) -> Result<Vec<Option<String>>, Error> {
+        let column = self
+            .batch
+            .column_by_name(name)
+            .ok_or_else(|| self.missing_column(name, DataType::Utf8))?;
+        match column.data_type() {
+            DataType::Utf8 => Ok(column
+                .as_string::<i32>()
+                .iter()
+                .map(|s| s.map(str::to_string))
+                .collect()),
+            DataType::LargeUtf8 => Ok(column
+                .as_string::<i64>()
+                .iter()
+                .map(|s| s.map(str::to_string))
+                .collect()),
+            _ => Err(self.missing_column(name, DataType::Utf8)),
+        }
+    }
+
+    /// A timestamp column, like the `time` column that every results table has.
+    ///
+    /// Julia writes millisecond timestamps, but other units are accepted as well.
+    pub fn times(&self, name: &str) -> Result<Vec<NaiveDateTime>, Error> {
+        let expected = DataType::Timestamp(TimeUnit::Millisecond, None);
+        let column = self
+            .batch
+            .column_by_name(name)
+            .ok_or_else(|| self.missing_column(name, expected.clone()))?;
+        let (values, per_second): (Vec<Option<i64>>, i64) = match column.data_type() {
+            DataType::Timestamp(TimeUnit::Second, _) => {
+                (timestamps::<TimestampSecondType>(column), 1)
+            }
+            DataType::Timestamp(TimeUnit::Millisecond, _) => {
+                (timestamps::<TimestampMillisecondType>(column), 1_000)
+            }
+            DataType::Timestamp(TimeUnit::Microsecond, _) => {
+                (timestamps::<TimestampMicrosecondType>(column), 1_000_000)
+            }
+            DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+                (timestamps::<TimestampNanosecondType>(column), 1_000_000_000)
+            }
+            _ => return Err(self.missing_column(name, expected)),
+        };
+        values
+            .into_iter()
+            .map(|value| {
+                value
+                    .and_then(|value| {
+                        let nanos = (value.rem_euclid(per_second)) * (1_000_000_000 / per_second);
+                        DateTime::from_timestamp(value.div_euclid(per_second), nanos as u32)
+                    })
+                    .map(|datetime| datetime.naive_utc())
+                    .ok_or_else(|| Error::InvalidResults {
+                        path: self.path.clone(),
+                        message: format!("column {name} contains an invalid or missing time"),
+                    })
+            })
+            .collect()
+    }
+
+    fn missing_column(&self, name: &str, data_type: DataType) -> Error {
+        Error::InvalidResults {
+            path: self.path.clone(),
+            message: format!("column {name} is missing or is not of type {data_type}"),
+        }
+    }
+}
+
+fn timestamps<T: ArrowPrimitiveType<Native = i64>>(column: &dyn Array) -> Vec<Option<i64>> {
+    column.as_primitive::<T>().iter().collect()
+}
+
+/// The length in seconds of the period that starts at each of the given times.
+///
+/// Results rows are stamped with the start of the period they describe, and the last
+/// period ends at the `endtime` of the simulation.
+pub fn period_lengths(times: &[NaiveDateTime], endtime: NaiveDateTime) -> Vec<f64> {
+    let mut unique = times.to_vec();
+    unique.sort_unstable();
+    unique.dedup();
+    times
+        .iter()
+        .map(|time| {
+            let i = unique.partition_point(|t| t <= time);
+            let end = unique.get(i).copied().unwrap_or(endtime);
+            (end - *time).num_milliseconds() as f64 / 1000.0
+        })
+        .collect()
+}
@@ -0,0 +1,143 @@
+//! Running a model, either in one go with `execute`, or step by step through the BMI.
+//!
+//! Julia is started once with `start`, after which any number of models can be run.
+
+use std::{
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use ribasim::{
+    bmi::Model,
+    config::{datetime_since, Config},
+    error::{Error, Status},
+    library::LibRibasim,
+};
+use tempfile::NamedTempFile;
+
+use crate::{
+    overrides,
+    progress::{Progress, ProgressFormat},
+    RunOptions,
+};
+
+/// Load libribasim and initialize Julia.
+pub fn start(options: &RunOptions) -> Result<LibRibasim, Error> {
+    let lib = LibRibasim::load(options.library.lib.as_deref(), options.library.verbose)?;
+    if options.library.verbose {
+        eprintln!("Loaded {}", lib.path.display());
+    }
+    lib.init_julia()?;
+    Ok(lib)
+}
+
+/// Stop runs on SIGINT or SIGTERM by setting the returned flag, after the current time
+/// step of a stepwise run, or else after the current model. A second signal exits right
+/// away.
+///
+/// Julia installs its own SIGINT handler during initialization, which would throw an
+/// InterruptException somewhere inside the solver. Call this after `start` to replace it.
+pub fn handle_interrupts(stepwise: bool) -> Result<Arc<AtomicBool>, Error> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    let until = if stepwise { "time step" } else { "model" };
+    ctrlc::set_handler(move || {
+        if flag.swap(true, Ordering::SeqCst) {
+            eprintln!("Interrupted again, exiting without writing results.");
+            process::exit(Status::Interrupted as i32);
+        }
+        eprintln!("Interrupted, stopping after the current {until}. Press Ctrl-C again to abort.");
+    })
+    .map_err(|err| Error::SignalHandler(err.to_string()))?;
+    Ok(interrupted)
+}
+
+/// Run a model with `Ribasim.main`, like the Julia CLI does.
+pub fn execute(lib: &LibRibasim, toml_path: &Path, options: &RunOptions) -> Result<Status, Error> {
+    let (_, resolved) = resolve(toml_path, &options.overrides.set)?;
+    let toml_path = resolved.as_ref().map_or(toml_path, |file| file.path());
+
+    match lib.execute(toml_path)? {
+        0 => Ok(Status::Success),
+        // `Ribasim.main` already reported why the model failed
+        1 => Ok(Status::ModelFailed),
+        code => Err(Error::ModelFailed(code)),
+    }
+}
+
+/// Run a model through `initialize`, `update` and `finalize`.
+///
+/// When `interrupted` is set, the simulation stops after the current time step and the
+/// model is finalized, which writes the results up to that moment.
+///
+/// The C API has no counterpart of the logging that `Ribasim.main` sets up, so there is
+/// no `ribasim.log`, the `[logging]` settings do not apply, and a different
+/// `ribasim_version` is not warned about.
+pub fn stepwise(
+    lib: &LibRibasim,
+    toml_path: &Path,
+    options: &RunOptions,
+    interrupted: &AtomicBool,
+) -> Result<Status, Error> {
+    let (config, resolved) = resolve(toml_path, &options.overrides.set)?;
+    let toml_path = resolved.as_ref().map_or(toml_path, |file| file.path());
+
+    let mut model = Model::initialize(lib, toml_path)?;
+    let end_time = model.end_time()?;
+    let mut time = model.current_time()?;
+    let mut progress = options
+        .progress
+        .or_else(ProgressFormat::detect)
+        .map(|format| {
+            let interval = Duration::from_secs_f64(options.progress_interval.max(0.0));
+            Progress::new(format, interval, config.starttime, time, end_time)
+        });
+    while time < end_time && !interrupted.load(Ordering::SeqCst) {
+        model.update()?;
+        let previous = time;
+        time = model.current_time()?;
+        if time <= previous {
+            // The solver failed, and further steps would not advance the model
+            model.finalize()?;
+            return Err(Error::Stalled(datetime_since(time, config.starttime)));
+        }
+        if let Some(progress) = &mut progress {
+            progress.update(time);
+        }
+    }
+    if let Some(progress) = &mut progress {
+        progress.finish(time);
+    }
+    model.finalize()?;
+
+    if interrupted.load(Ordering::SeqCst) {
+        eprintln!(
+            "Stopped at model time {}, results up to this moment are written to {}",
+            datetime_since(time, config.starttime),
+            config.dir.join(&config.results_dir).display()
+        );
+        return Ok(Status::Interrupted);
+    }
+    Ok(Status::Success)
+}
+
+/// Read the configuration with its overrides, and write a resolved TOML file if there
+/// are any.
+///
+/// The resolved file is removed when the returned handle is dropped.
+fn resolve(toml_path: &Path, set: &[String]) -> Result<(Config, Option<NamedTempFile>), Error> {
+    if !toml_path.is_file() {
+        return Err(Error::TomlNotFound(toml_path.to_owned()));
+    }
+    let (config, table) = overrides::load(toml_path, set)?;
+    if set.is_empty() {
+        return Ok((config, None));
+    }
+    let file = overrides::write_resolved(&config, table).map_err(Error::ResolvedToml)?;
+    Ok((config, Some(file)))
+}
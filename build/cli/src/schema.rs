@@ -0,0 +1,565 @@
+//! The input tables of a model and their columns, as declared in `core/src/schema.jl`.
+
+use std::fmt;
+
+use arrow::datatypes::DataType;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use crate::config::node_type;
+
+/// The Julia type of a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ColumnType {
+    Int32,
+    Int64,
+    Float64,
+    Bool,
+    String,
+    DateTime,
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl ColumnType {
+    /// Whether the Julia core can convert values stored as `stored` to this type.
+    pub fn accepts(self, stored: StoredType) -> bool {
+        match self {
+            ColumnType::Int32 | ColumnType::Int64 => stored == StoredType::Integer,
+            ColumnType::Float64 => matches!(stored, StoredType::Float | StoredType::Integer),
+            // The database has no boolean type, pandas stores them as integers
+            ColumnType::Bool => matches!(stored, StoredType::Boolean | StoredType::Integer),
+            ColumnType::String => stored == StoredType::Text,
+            ColumnType::DateTime => stored == StoredType::Time,
+        }
+    }
+}
+
+/// How the values of a column are stored, in the database or in an Arrow file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoredType {
+    Integer,
+    Float,
+    Boolean,
+    Text,
+    Time,
+    Other,
+}
+
+impl fmt::Display for StoredType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            StoredType::Integer => "integer",
+            StoredType::Float => "float",
+            StoredType::Boolean => "boolean",
+            StoredType::Text => "text",
+            StoredType::Time => "date-time",
+            StoredType::Other => "an unsupported type",
+        };
+        f.write_str(name)
+    }
+}
+
+impl StoredType {
+    /// The storage of a database column, from its declared type like SQLite
+    /// determines the column affinity.
+    pub fn from_sqlite(declared: &str) -> StoredType {
+        let declared = declared.to_uppercase();
+        if declared.contains("INT") {
+            StoredType::Integer
+        } else if declared.contains("TIMESTAMP") || declared.contains("DATE") {
+            StoredType::Time
+        } else if declared.contains("BOOL") {
+            StoredType::Boolean
+        } else if ["CHAR", "CLOB", "TEXT"]
+            .iter()
+            .any(|affinity| declared.contains(affinity))
+        {
+            StoredType::Text
+        } else if ["REAL", "FLOA", "DOUB"]
+            .iter()
+            .any(|affinity| declared.contains(affinity))
+        {
+            StoredType::Float
+        } else {
+            StoredType::Other
+        }
+    }
+
+    pub fn from_arrow(data_type: &DataType) -> StoredType {
+        match data_type {
+            DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64 => StoredType::Integer,
+            DataType::Float16 | DataType::Float32 | DataType::Float64 => StoredType::Float,
+            DataType::Boolean => StoredType::Boolean,
+            DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => StoredType::Text,
+            DataType::Timestamp(_, _) | DataType::Date32 | DataType::Date64 => StoredType::Time,
+            DataType::Dictionary(_, value) => StoredType::from_arrow(value),
+            _ => StoredType::Other,
+        }
+    }
+}
+
+/// A column of an input table.
+#[derive(Debug, Serialize)]
+pub struct Column {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub column_type: ColumnType,
+    /// Whether values may be missing, `Union{Missing, T}` in Julia.
+    pub nullable: bool,
+}
+
+const fn required(name: &'static str, column_type: ColumnType) -> Column {
+    Column {
+        name,
+        column_type,
+        nullable: false,
+    }
+}
+
+const fn nullable(name: &'static str, column_type: ColumnType) -> Column {
+    Column {
+        name,
+        column_type,
+        nullable: true,
+    }
+}
+
+/// The schema of an input table, like `ribasim.basin.profile`.
+#[derive(Debug)]
+pub struct Schema {
+    /// The TOML section of the node type, like `basin`.
+    pub section: &'static str,
+    pub kind: &'static str,
+    pub columns: &'static [Column],
+}
+
+impl Schema {
+    /// The name of the schema, like `ribasim.tabulatedratingcurve.time`.
+    pub fn name(&self) -> String {
+        format!("ribasim.{}.{}", self.section.replace('_', ""), self.kind)
+    }
+
+    /// The name of the table in the database, like `TabulatedRatingCurve / time`.
+    pub fn table_name(&self) -> String {
+        format!("{} / {}", node_type(self.section), self.kind)
+    }
+
+    pub fn column(&self, name: &str) -> Option<&'static Column> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    /// Check the columns of a table against the schema, returning the problems found.
+    ///
+    /// Columns prefixed with `meta_` are for the user and are not checked, and `fid` is
+    /// the primary key of tables in the database.
+    pub fn check(&self, columns: &[(String, StoredType)]) -> Vec<String> {
+        let mut problems = Vec::new();
+        for column in self.columns {
+            if !columns.iter().any(|(name, _)| name == column.name) {
+                problems.push(format!("column {} is missing", column.name));
+            }
+        }
+        for (name, stored) in columns {
+            if name.starts_with("meta_") || name == "fid" {
+                continue;
+            }
+            match self.column(name) {
+                Some(column) if !column.column_type.accepts(*stored) => problems.push(format!(
+                    "column {name} is {stored}, expected {}",
+                    column.column_type
+                )),
+                Some(_) => {}
+                None => problems.push(format!(
+                    "column {name} is not in the schema, prefix it with meta_ to keep it"
+                )),
+            }
+        }
+        problems
+    }
+}
+
+impl Serialize for Schema {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Schema", 5)?;
+        state.serialize_field("name", &self.name())?;
+        state.serialize_field("table", &self.table_name())?;
+        state.serialize_field("section", self.section)?;
+        state.serialize_field("kind", self.kind)?;
+        state.serialize_field("columns", self.columns)?;
+        state.end()
+    }
+}
+
+/// Find a schema by its name like `ribasim.basin.profile`, its database table name like
+/// `Basin / profile`, or its TOML section and kind like `basin.profile`.
+pub fn find(name: &str) -> Option<&'static Schema> {
+    let short = name.strip_prefix("ribasim.").unwrap_or(name);
+    SCHEMAS.iter().find(|schema| {
+        let (section, kind) = short.split_once('.').unwrap_or((short, ""));
+        (kind == schema.kind
+            && (section == schema.section || section == schema.section.replace('_', "")))
+            || name.eq_ignore_ascii_case(&schema.table_name())
+    })
+}
+
+/// All input tables besides Node and Edge, in the order of [`crate::config::NODE_TABLES`].
+pub const SCHEMAS: [Schema; 33] = {
+    use ColumnType::{Bool, DateTime, Float64, Int32, Int64, String};
+    [
+        Schema {
+            section: "basin",
+            kind: "static",
+            columns: &[
+                required("node_id", Int32),
+                nullable("drainage", Float64),
+                nullable("potential_evaporation", Float64),
+                nullable("infiltration", Float64),
+                nullable("precipitation", Float64),
+                nullable("urban_runoff", Float64),
+            ],
+        },
+        Schema {
+            section: "basin",
+            kind: "time",
+            columns: &[
+                required("node_id", Int32),
+                required("time", DateTime),
+                nullable("drainage", Float64),
+                nullable("potential_evaporation", Float64),
+                nullable("infiltration", Float64),
+                nullable("precipitation", Float64),
+                nullable("urban_runoff", Float64),
+            ],
+        },
+        Schema {
+            section: "basin",
+            kind: "profile",
+            columns: &[
+                required("node_id", Int32),
+                required("area", Float64),
+                required("level", Float64),
+            ],
+        },
+        Schema {
+            section: "basin",
+            kind: "state",
+            columns: &[required("node_id", Int32), required("level", Float64)],
+        },
+        Schema {
+            section: "basin",
+            kind: "subgrid",
+            columns: &[
+                required("subgrid_id", Int32),
+                required("node_id", Int32),
+                required("basin_level", Float64),
+                required("subgrid_level", Float64),
+            ],
+        },
+        Schema {
+            section: "basin",
+            kind: "concentration",
+            columns: &[
+                required("node_id", Int32),
+                required("time", DateTime),
+                required("substance", String),
+                nullable("drainage", Float64),
+                nullable("precipitation", Float64),
+            ],
+        },
+        Schema {
+            section: "basin",
+            kind: "concentrationexternal",
+            columns: &[
+                required("node_id", Int32),
+                required("time", DateTime),
+                required("substance", String),
+                nullable("concentration", Float64),
+            ],
+        },
+        Schema {
+            section: "basin",
+            kind: "concentrationstate",
+            columns: &[
+                required("node_id", Int32),
+                required("substance", String),
+                nullable("concentration", Float64),
+            ],
+        },
+        Schema {
+            section: "discrete_control",
+            kind: "variable",
+            columns: &[
+                required("node_id", Int32),
+                required("compound_variable_id", Int32),
+                required("listen_node_type", String),
+                required("listen_node_id", Int32),
+                required("variable", String),
+                nullable("weight", Float64),
+                nullable("look_ahead", Float64),
+            ],
+        },
+        Schema {
+            section: "discrete_control",
+            kind: "condition",
+            columns: &[
+                required("node_id", Int32),
+                required("compound_variable_id", Int32),
+                required("greater_than", Float64),
+            ],
+        },
+        Schema {
+            section: "discrete_control",
+            kind: "logic",
+            columns: &[
+                required("node_id", Int32),
+                required("truth_state", String),
+                required("control_state", String),
+            ],
+        },
+        Schema {
+            section: "flow_boundary",
+            kind: "static",
+            columns: &[
+                required("node_id", Int32),
+                nullable("active", Bool),
+                required("flow_rate", Float64),
+            ],
+        },
+        Schema {
+            section: "flow_boundary",
+            kind: "time",
+            columns: &[
+                required("node_id", Int32),
+                required("time", DateTime),
+                required("flow_rate", Float64),
+            ],
+        },
+        Schema {
+            section: "flow_boundary",
+            kind: "concentration",
+            columns: &[
+                required("node_id", Int32),
+                required("time", DateTime),
+                required("substance", String),
+                required("concentration", Float64),
+            ],
+        },
+        Schema {
+            section: "flow_demand",
+            kind: "static",
+            columns: &[
+                required("node_id", Int64),
+                required("demand", Float64),
+                required("priority", Int32),
+            ],
+        },
+        Schema {
+            section: "flow_demand",
+            kind: "time",
+            columns: &[
+                required("node_id", Int64),
+                required("time", DateTime),
+                required("demand", Float64),
+                required("priority", Int32),
+            ],
+        },
+        Schema {
+            section: "fractional_flow",
+            kind: "static",
+            columns: &[
+                required("node_id", Int32),
+                required("fraction", Float64),
+                nullable("control_state", String),
+            ],
+        },
+        Schema {
+            section: "level_boundary",
+            kind: "static",
+            columns: &[
+                required("node_id", Int32),
+                nullable("active", Bool),
+                required("level", Float64),
+            ],
+        },
+        Schema {
+            section: "level_boundary",
+            kind: "time",
+            columns: &[
+                required("node_id", Int32),
+                required("time", DateTime),
+                required("level", Float64),
+            ],
+        },
+        Schema {
+            section: "level_boundary",
+            kind: "concentration",
+            columns: &[
+                required("node_id", Int32),
+                required("time", DateTime),
+                required("substance", String),
+                required("concentration", Float64),
+            ],
+        },
+        Schema {
+            section: "level_demand",
+            kind: "static",
+            columns: &[
+                required("node_id", Int32),
+                nullable("min_level", Float64),
+                nullable("max_level", Float64),
+                required("priority", Int32),
+            ],
+        },
+        Schema {
+            section: "level_demand",
+            kind: "time",
+            columns: &[
+                required("node_id", Int32),
+                required("time", DateTime),
+                nullable("min_level", Float64),
+                nullable("max_level", Float64),
+                required("priority", Int32),
+            ],
+        },
+        Schema {
+            section: "linear_resistance",
+            kind: "static",
+            columns: &[
+                required("node_id", Int32),
+                nullable("active", Bool),
+                required("resistance", Float64),
+                nullable("max_flow_rate", Float64),
+                nullable("control_state", String),
+            ],
+        },
+        Schema {
+            section: "manning_resistance",
+            kind: "static",
+            columns: &[
+                required("node_id", Int32),
+                nullable("active", Bool),
+                required("length", Float64),
+                required("manning_n", Float64),
+                required("profile_width", Float64),
+                required("profile_slope", Float64),
+                nullable("control_state", String),
+            ],
+        },
+        Schema {
+            section: "outlet",
+            kind: "static",
+            columns: &[
+                required("node_id", Int32),
+                nullable("active", Bool),
+                required("flow_rate", Float64),
+                nullable("min_flow_rate", Float64),
+                nullable("max_flow_rate", Float64),
+                nullable("min_crest_level", Float64),
+                nullable("control_state", String),
+            ],
+        },
+        Schema {
+            section: "pid_control",
+            kind: "static",
+            columns: &[
+                required("node_id", Int32),
+                nullable("active", Bool),
+                required("listen_node_type", String),
+                required("listen_node_id", Int32),
+                required("target", Float64),
+                required("proportional", Float64),
+                required("integral", Float64),
+                required("derivative", Float64),
+                nullable("control_state", String),
+            ],
+        },
+        Schema {
+            section: "pid_control",
+            kind: "time",
+            columns: &[
+                required("node_id", Int32),
+                required("listen_node_type", String),
+                required("listen_node_id", Int32),
+                required("time", DateTime),
+                required("target", Float64),
+                required("proportional", Float64),
+                required("integral", Float64),
+                required("derivative", Float64),
+                nullable("control_state", String),
+            ],
+        },
+        Schema {
+            section: "pump",
+            kind: "static",
+            columns: &[
+                required("node_id", Int32),
+                nullable("active", Bool),
+                required("flow_rate", Float64),
+                nullable("min_flow_rate", Float64),
+                nullable("max_flow_rate", Float64),
+                nullable("control_state", String),
+            ],
+        },
+        Schema {
+            section: "tabulated_rating_curve",
+            kind: "static",
+            columns: &[
+                required("node_id", Int32),
+                nullable("active", Bool),
+                required("level", Float64),
+                required("flow_rate", Float64),
+                nullable("control_state", String),
+            ],
+        },
+        Schema {
+            section: "tabulated_rating_curve",
+            kind: "time",
+            columns: &[
+                required("node_id", Int32),
+                required("time", DateTime),
+                required("level", Float64),
+                required("flow_rate", Float64),
+            ],
+        },
+        Schema {
+            section: "terminal",
+            kind: "static",
+            columns: &[required("node_id", Int32)],
+        },
+        Schema {
+            section: "user_demand",
+            kind: "static",
+            columns: &[
+                required("node_id", Int32),
+                nullable("active", Bool),
+                nullable("demand", Float64),
+                required("return_factor", Float64),
+                required("min_level", Float64),
+                required("priority", Int32),
+            ],
+        },
+        Schema {
+            section: "user_demand",
+            kind: "time",
+            columns: &[
+                required("node_id", Int32),
+                required("time", DateTime),
+                required("demand", Float64),
+                required("return_factor", Float64),
+                required("min_level", Float64),
+                required("priority", Int32),
+            ],
+        },
+    ]
+};
@@ -0,0 +1,300 @@
+//! Water balance summary of the results of a simulation.
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDateTime;
+use ribasim::{
+    config::Config,
+    error::Error,
+    results::{period_lengths, Table},
+};
+use serde::Serialize;
+
+use crate::table;
+
+/// Volumes in m³ of a single basin over the whole simulation.
+#[derive(Debug, Default, Serialize)]
+pub struct BasinTotals {
+    pub node_id: i32,
+    pub inflow: f64,
+    pub outflow: f64,
+    pub precipitation: f64,
+    pub evaporation: f64,
+    pub drainage: f64,
+    pub infiltration: f64,
+    pub storage_change: f64,
+    pub balance_error: f64,
+    /// The largest absolute relative error of any time step.
+    pub max_relative_error: f64,
+}
+
+/// The time step and basin with the largest absolute relative error.
+#[derive(Debug, Serialize)]
+pub struct WorstError {
+    pub time: NaiveDateTime,
+    pub node_id: i32,
+    pub relative_error: f64,
+    pub balance_error: f64,
+}
+
+/// Volumes in m³ over the whole simulation that cross the boundary of the model, from
+/// flow.arrow.
+#[derive(Debug, Default, Serialize)]
+pub struct BoundaryTotals {
+    /// Flow out of FlowBoundary and LevelBoundary nodes.
+    pub boundary_inflow: f64,
+    /// Flow into LevelBoundary and Terminal nodes.
+    pub boundary_outflow: f64,
+    /// Flow into UserDemand nodes.
+    pub user_abstraction: f64,
+    /// Flow out of UserDemand nodes.
+    pub user_return: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub starttime: NaiveDateTime,
+    pub endtime: NaiveDateTime,
+    pub basins: Vec<BasinTotals>,
+    pub worst_relative_error: Option<WorstError>,
+    /// Absent if there is no flow.arrow.
+    pub boundaries: Option<BoundaryTotals>,
+}
+
+impl Summary {
+    /// Integrate the basin results, and the flow results if present, over time.
+    pub fn from_results(config: &Config) -> Result<Summary, Error> {
+        let basin = Table::read_results(config, "basin")?;
+        let time = basin.times("time")?;
+        let node_id = basin.i32s("node_id")?;
+        let dt = period_lengths(&time, config.endtime);
+        let column = |name| basin.f64s(name);
+        let (inflow, outflow, storage_rate) = (
+            column("inflow_rate")?,
+            column("outflow_rate")?,
+            column("storage_rate")?,
+        );
+        let (precipitation, evaporation, drainage, infiltration) = (
+            column("precipitation")?,
+            column("evaporation")?,
+            column("drainage")?,
+            column("infiltration")?,
+        );
+        let (balance_error, relative_error) = (column("balance_error")?, column("relative_error")?);
+
+        let mut basins: BTreeMap<i32, BasinTotals> = BTreeMap::new();
+        let mut worst: Option<WorstError> = None;
+        for i in 0..basin.num_rows() {
+            let Some(node_id) = node_id[i] else {
+                continue;
+            };
+            let totals = basins.entry(node_id).or_insert_with(|| BasinTotals {
+                node_id,
+                ..Default::default()
+            });
+            totals.inflow += inflow[i] * dt[i];
+            totals.outflow += outflow[i] * dt[i];
+            totals.precipitation += precipitation[i] * dt[i];
+            totals.evaporation += evaporation[i] * dt[i];
+            totals.drainage += drainage[i] * dt[i];
+            totals.infiltration += infiltration[i] * dt[i];
+            totals.storage_change += storage_rate[i] * dt[i];
+            totals.balance_error += balance_error[i] * dt[i];
+
+            // NaN sorts above infinity, so it is reported as the worst
+            let error = relative_error[i].abs();
+            if error.total_cmp(&totals.max_relative_error).is_gt() {
+                totals.max_relative_error = error;
+            }
+            if worst
+                .as_ref()
+                .is_none_or(|worst| error.total_cmp(&worst.relative_error.abs()).is_gt())
+            {
+                worst = Some(WorstError {
+                    time: time[i],
+                    node_id,
+                    relative_error: relative_error[i],
+                    balance_error: balance_error[i],
+                });
+            }
+        }
+
+        let boundaries = match Table::read_results(config, "flow") {
+            Ok(flow) => Some(boundary_totals(&flow, config.endtime)?),
+            Err(Error::ResultsNotFound(_)) => None,
+            Err(err) => return Err(err),
+        };
+
+        Ok(Summary {
+            starttime: config.starttime,
+            endtime: config.endtime,
+            basins: basins.into_values().collect(),
+            worst_relative_error: worst,
+            boundaries,
+        })
+    }
+
+    pub fn print(&self) {
+        println!(
+            "Water balance from {} to {}, volumes in m³",
+            self.starttime, self.endtime
+        );
+        println!();
+        let rows: Vec<Vec<String>> = self
+            .basins
+            .iter()
+            .map(|basin| {
+                let mut row = vec![basin.node_id.to_string()];
+                row.extend(
+                    [
+                        basin.inflow,
+                        basin.outflow,
+                        basin.precipitation,
+                        basin.evaporation,
+                        basin.drainage,
+                        basin.infiltration,
+                        basin.storage_change,
+                        basin.balance_error,
+                        basin.max_relative_error,
+                    ]
+                    .map(table::number),
+                );
+                row
+            })
+            .collect();
+        table::print(
+            &[
+                "node_id",
+                "inflow",
+                "outflow",
+                "precipitation",
+                "evaporation",
+                "drainage",
+                "infiltration",
+                "storage_change",
+                "balance_error",
+                "max_relative_error",
+            ],
+            &rows,
+        );
+
+        if let Some(worst) = &self.worst_relative_error {
+            println!();
+            println!(
+                "Worst relative error: {} at Basin #{} in the period starting {} (balance error {} m³/s)",
+                table::number(worst.relative_error),
+                worst.node_id,
+                worst.time,
+                table::number(worst.balance_error)
+            );
+        }
+
+        if let Some(boundaries) = &self.boundaries {
+            println!();
+            println!(
+                "Boundary inflow:  {}",
+                table::number(boundaries.boundary_inflow)
+            );
+            println!(
+                "Boundary outflow: {}",
+                table::number(boundaries.boundary_outflow)
+            );
+            println!(
+                "User abstraction: {}",
+                table::number(boundaries.user_abstraction)
+            );
+            println!(
+                "User return:      {}",
+                table::number(boundaries.user_return)
+            );
+        }
+    }
+}
+
+/// A basin time step with a water balance error above the limit.
+#[derive(Debug)]
+pub struct Violation {
+    pub time: NaiveDateTime,
+    pub node_id: i32,
+    pub balance_error: f64,
+    pub relative_error: f64,
+}
+
+/// The rows of basin.arrow whose absolute `relative_error` or `balance_error` exceeds
+/// the given limits. NaN errors always exceed them.
+pub fn balance_violations(
+    config: &Config,
+    max_relative_error: Option<f64>,
+    max_balance_error: Option<f64>,
+) -> Result<Vec<Violation>, Error> {
+    let basin = Table::read_results(config, "basin")?;
+    let time = basin.times("time")?;
+    let node_id = basin.i32s("node_id")?;
+    let balance_error = basin.f64s("balance_error")?;
+    let relative_error = basin.f64s("relative_error")?;
+
+    let exceeds = |error: f64, limit: Option<f64>| {
+        limit.is_some_and(|limit| error.is_nan() || error.abs() > limit)
+    };
+    Ok((0..basin.num_rows())
+        .filter(|&i| {
+            exceeds(relative_error[i], max_relative_error)
+                || exceeds(balance_error[i], max_balance_error)
+        })
+        .map(|i| Violation {
+            time: time[i],
+            node_id: node_id[i].unwrap_or_default(),
+            balance_error: balance_error[i],
+            relative_error: relative_error[i],
+        })
+        .collect())
+}
+
+/// The number of violations that are listed, the rest is only counted.
+const MAX_LISTED: usize = 50;
+
+pub fn print_violations(violations: &[Violation]) {
+    let rows: Vec<Vec<String>> = violations
+        .iter()
+        .take(MAX_LISTED)
+        .map(|violation| {
+            vec![
+                violation.time.to_string(),
+                violation.node_id.to_string(),
+                table::number(violation.balance_error),
+                table::number(violation.relative_error),
+            ]
+        })
+        .collect();
+    table::eprint(
+        &["time", "node_id", "balance_error", "relative_error"],
+        &rows,
+    );
+    if violations.len() > MAX_LISTED {
+        eprintln!("... and {} more", violations.len() - MAX_LISTED);
+    }
+}
+
+fn boundary_totals(flow: &Table, endtime: NaiveDateTime) -> Result<BoundaryTotals, Error> {
+    let time = flow.times("time")?;
+    let dt = period_lengths(&time, endtime);
+    let from_node_type = flow.strings("from_node_type")?;
+    let to_node_type = flow.strings("to_node_type")?;
+    let flow_rate = flow.f64s("flow_rate")?;
+
+    let mut totals = BoundaryTotals::default();
+    for i in 0..flow.num_rows() {
+        let volume = flow_rate[i] * dt[i];
+        match from_node_type[i].as_deref() {
+            Some("FlowBoundary" | "LevelBoundary") => totals.boundary_inflow += volume,
+            Some("UserDemand") => totals.user_return += volume,
+            _ => {}
+        }
+        match to_node_type[i].as_deref() {
+            Some("LevelBoundary" | "Terminal") => totals.boundary_outflow += volume,
+            Some("UserDemand") => totals.user_abstraction += volume,
+            _ => {}
+        }
+    }
+    Ok(totals)
+}
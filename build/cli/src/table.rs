@@ -0,0 +1,79 @@
+//! Plain-text tables for the reports of the CLI.
+
+/// Print rows under a header, with numeric columns aligned to the right.
+pub fn print(header: &[&str], rows: &[Vec<String>]) {
+    for line in format(header, rows) {
+        println!("{line}");
+    }
+}
+
+/// Print a table to stderr, like [`print`].
+pub fn eprint(header: &[&str], rows: &[Vec<String>]) {
+    for line in format(header, rows) {
+        eprintln!("{line}");
+    }
+}
+
+fn format(header: &[&str], rows: &[Vec<String>]) -> Vec<String> {
+    let mut widths: Vec<usize> = header.iter().map(|name| name.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let numeric: Vec<bool> = (0..header.len())
+        .map(|i| {
+            rows.iter()
+                .all(|row| row.get(i).is_some_and(|cell| cell.parse::<f64>().is_ok()))
+        })
+        .collect();
+
+    let format_row = |cells: &mut dyn Iterator<Item = &str>| {
+        let line: Vec<String> = cells
+            .zip(&widths)
+            .zip(&numeric)
+            .map(|((cell, &width), &numeric)| {
+                if numeric {
+                    format!("{cell:>width$}")
+                } else {
+                    format!("{cell:<width$}")
+                }
+            })
+            .collect();
+        line.join("  ").trim_end().to_string()
+    };
+    let mut lines = vec![format_row(&mut header.iter().copied())];
+    lines.extend(
+        rows.iter()
+            .map(|row| format_row(&mut row.iter().map(String::as_str))),
+    );
+    lines
+}
+
+/// Format a number compactly, switching to scientific notation for large and small
+/// magnitudes.
+pub fn number(value: f64) -> String {
+    let magnitude = value.abs();
+    if value == 0.0 || !value.is_finite() || (1e-3..1e7).contains(&magnitude) {
+        format!("{value:.3}")
+    } else {
+        format!("{value:.3e}")
+    }
+}
+
+/// Format seconds like `2d 03:00:00`.
+pub fn duration(seconds: f64) -> String {
+    let seconds = seconds.round() as i64;
+    let (days, rest) = (seconds / 86400, seconds % 86400);
+    let time = format!(
+        "{:02}:{:02}:{:02}",
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    );
+    if days > 0 {
+        format!("{days}d {time}")
+    } else {
+        time
+    }
+}
@@ -0,0 +1,525 @@
+//! Moving input tables between the GeoPackage database and external Arrow files.
+//!
+//! Any table other than Node and Edge can be stored in the database, or in an Arrow
+//! file that the TOML refers to from the section of its node type, like
+//! `[basin] time = "basin/time.arrow"`.
+
+use std::{
+    collections::HashSet,
+    error::Error as StdError,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arrow::{
+    array::{
+        Array, ArrayRef, BooleanBuilder, Float64Array, Float64Builder, Int32Builder, Int64Array,
+        Int64Builder, RecordBatch, StringArray, StringBuilder, TimestampMillisecondArray,
+        TimestampMillisecondBuilder,
+    },
+    compute::cast,
+    datatypes::{DataType, Field, Schema as ArrowSchema, TimeUnit},
+    ipc::{root_as_footer, root_as_message, CompressionType},
+};
+use chrono::DateTime;
+use clap::ValueEnum;
+use ribasim::{
+    config::{parse_datetime, Config, ConfigError},
+    error::Error,
+    geopackage::{quote, Database},
+    results::{self, Table},
+    schema::{ColumnType, Schema, StoredType},
+};
+use rusqlite::types::{Value, ValueRef};
+use toml_edit::DocumentMut;
+
+/// Compression of the Arrow file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ArrowCompression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl ArrowCompression {
+    pub fn codec(self) -> Option<CompressionType> {
+        match self {
+            ArrowCompression::None => None,
+            ArrowCompression::Lz4 => Some(CompressionType::LZ4_FRAME),
+            ArrowCompression::Zstd => Some(CompressionType::ZSTD),
+        }
+    }
+}
+
+/// Move a table from the database to an Arrow file, and refer to it from the TOML.
+///
+/// `output` is relative to the `input_dir`, like the paths in the TOML. Text columns
+/// whose values repeat are dictionary encoded if `dictionary` is set. Returns the path
+/// of the Arrow file.
+pub fn extract(
+    toml_path: &Path,
+    table: &Schema,
+    output: Option<&str>,
+    compression: ArrowCompression,
+    dictionary: bool,
+) -> Result<PathBuf, Error> {
+    let config = Config::from_path(toml_path)?;
+    let name = table.table_name();
+    if let Some(path) = reference(&config, table) {
+        return Err(Error::InvalidArgument(format!(
+            "{name} is already read from {path}"
+        )));
+    }
+    let relative = output.map_or_else(
+        || format!("{}/{}.arrow", table.section, table.kind),
+        str::to_string,
+    );
+    let path = config.input_path(&relative);
+    if path.exists() {
+        return Err(Error::InvalidArgument(format!(
+            "{} already exists",
+            path.display()
+        )));
+    }
+
+    let database = Database::open_writable(&config.database_path())?;
+    if !database.has_table(&name)? {
+        return Err(Error::InvalidArgument(format!(
+            "{name} is not in {}",
+            database.path.display()
+        )));
+    }
+    let batch = read_table(&database, table, dictionary)?;
+    results::write(&batch, compression.codec(), &path).map_err(|source| Error::Write {
+        path: path.clone(),
+        source,
+    })?;
+    set_reference(toml_path, table, Some(&relative))?;
+
+    let write_error = |source: rusqlite::Error| Error::Write {
+        path: database.path.clone(),
+        source: source.into(),
+    };
+    let connection = database.connection();
+    let transaction = connection.unchecked_transaction().map_err(write_error)?;
+    transaction
+        .execute(&format!("DROP TABLE {}", quote(&name)), [])
+        .map_err(write_error)?;
+    if database.has_table("gpkg_contents")? {
+        transaction
+            .execute("DELETE FROM gpkg_contents WHERE table_name = ?1", [&name])
+            .map_err(write_error)?;
+    }
+    transaction.commit().map_err(write_error)?;
+    // Give the space of the table back to the file system
+    connection.execute("VACUUM", []).map_err(write_error)?;
+    Ok(path)
+}
+
+/// Move a table from its Arrow file into the database, and remove the reference from
+/// the TOML. The Arrow file is left in place, returns its path.
+pub fn embed(toml_path: &Path, table: &Schema) -> Result<PathBuf, Error> {
+    let config = Config::from_path(toml_path)?;
+    let name = table.table_name();
+    let Some(relative) = reference(&config, table) else {
+        return Err(Error::InvalidArgument(format!(
+            "{name} is not read from an Arrow file in {}",
+            toml_path.display()
+        )));
+    };
+    let path = config.input_path(relative);
+    if !path.is_file() {
+        return Err(Error::InvalidArgument(format!(
+            "{} does not exist",
+            path.display()
+        )));
+    }
+    let arrow = Table::read(&path)?;
+
+    let database = Database::open_writable(&config.database_path())?;
+    if database.has_table(&name)? {
+        return Err(Error::InvalidArgument(format!(
+            "{name} is already in {}",
+            database.path.display()
+        )));
+    }
+    write_table(&database, &name, &arrow.batch)?;
+    set_reference(toml_path, table, None)?;
+    Ok(path)
+}
+
+/// The path of the Arrow file of a table as given in the TOML.
+fn reference<'a>(config: &'a Config, table: &Schema) -> Option<&'a str> {
+    config
+        .tables
+        .get(table.section)
+        .and_then(|paths| paths.get(table.kind))
+        .map(String::as_str)
+}
+
+/// Set or remove the path of a table in the TOML, keeping the rest of the file intact.
+fn set_reference(toml_path: &Path, table: &Schema, path: Option<&str>) -> Result<(), Error> {
+    let write_error = |source: Box<dyn StdError + Send + Sync>| Error::Write {
+        path: toml_path.to_owned(),
+        source,
+    };
+    let text =
+        fs::read_to_string(toml_path).map_err(|err| ConfigError::Io(toml_path.to_owned(), err))?;
+    let mut document: DocumentMut = text.parse().map_err(|err| write_error(Box::new(err)))?;
+    match path {
+        Some(path) => {
+            let section = document
+                .entry(table.section)
+                .or_insert_with(toml_edit::table)
+                .as_table_like_mut()
+                .ok_or_else(|| write_error(format!("{} is not a table", table.section).into()))?;
+            section.insert(table.kind, toml_edit::value(path));
+        }
+        None => {
+            if let Some(section) = document
+                .get_mut(table.section)
+                .and_then(|section| section.as_table_like_mut())
+            {
+                section.remove(table.kind);
+                if section.is_empty() {
+                    document.remove(table.section);
+                }
+            }
+        }
+    }
+    fs::write(toml_path, document.to_string()).map_err(|err| write_error(Box::new(err)))
+}
+
+/// A column being read from the database.
+enum Column {
+    Int32(Int32Builder),
+    Int64(Int64Builder),
+    Float64(Float64Builder),
+    Boolean(BooleanBuilder),
+    Utf8(StringBuilder),
+    Timestamp(TimestampMillisecondBuilder),
+}
+
+impl Column {
+    /// Choose the Arrow type from the schema, or for columns that are not in the schema,
+    /// like `meta_` columns, from the declared SQLite type.
+    fn new(column_type: Option<ColumnType>, declared: &str) -> Column {
+        let column_type = column_type.unwrap_or(match StoredType::from_sqlite(declared) {
+            StoredType::Integer => ColumnType::Int64,
+            StoredType::Float => ColumnType::Float64,
+            StoredType::Boolean => ColumnType::Bool,
+            StoredType::Time => ColumnType::DateTime,
+            StoredType::Text | StoredType::Other => ColumnType::String,
+        });
+        match column_type {
+            ColumnType::Int32 => Column::Int32(Int32Builder::new()),
+            ColumnType::Int64 => Column::Int64(Int64Builder::new()),
+            ColumnType::Float64 => Column::Float64(Float64Builder::new()),
+            ColumnType::Bool => Column::Boolean(BooleanBuilder::new()),
+            ColumnType::String => Column::Utf8(StringBuilder::new()),
+            ColumnType::DateTime => Column::Timestamp(TimestampMillisecondBuilder::new()),
+        }
+    }
+
+    fn append(&mut self, value: ValueRef) -> Result<(), String> {
+        let invalid = |type_name: &str| format!("cannot convert {value:?} to {type_name}");
+        match (self, value) {
+            (Column::Int32(builder), ValueRef::Null) => builder.append_null(),
+            (Column::Int64(builder), ValueRef::Null) => builder.append_null(),
+            (Column::Float64(builder), ValueRef::Null) => builder.append_null(),
+            (Column::Boolean(builder), ValueRef::Null) => builder.append_null(),
+            (Column::Utf8(builder), ValueRef::Null) => builder.append_null(),
+            (Column::Timestamp(builder), ValueRef::Null) => builder.append_null(),
+            (Column::Int32(builder), ValueRef::Integer(value)) => {
+                builder.append_value(i32::try_from(value).map_err(|_| invalid("a 32-bit integer"))?)
+            }
+            (Column::Int64(builder), ValueRef::Integer(value)) => builder.append_value(value),
+            (Column::Float64(builder), ValueRef::Integer(value)) => {
+                builder.append_value(value as f64)
+            }
+            (Column::Float64(builder), ValueRef::Real(value)) => builder.append_value(value),
+            (Column::Boolean(builder), ValueRef::Integer(value)) => {
+                builder.append_value(value != 0)
+            }
+            (Column::Utf8(builder), ValueRef::Text(text)) => {
+                builder.append_value(String::from_utf8_lossy(text))
+            }
+            (Column::Utf8(builder), ValueRef::Integer(value)) => {
+                builder.append_value(value.to_string())
+            }
+            (Column::Utf8(builder), ValueRef::Real(value)) => {
+                builder.append_value(value.to_string())
+            }
+            (Column::Timestamp(builder), ValueRef::Text(text)) => {
+                let time = parse_datetime(&String::from_utf8_lossy(text))
+                    .ok_or_else(|| invalid("a date-time"))?;
+                builder.append_value(time.and_utc().timestamp_millis())
+            }
+            (Column::Int32(_) | Column::Int64(_), _) => return Err(invalid("an integer")),
+            (Column::Boolean(_), _) => return Err(invalid("a boolean")),
+            (Column::Timestamp(_), _) => return Err(invalid("a date-time")),
+            (Column::Float64(_) | Column::Utf8(_), _) => return Err(invalid("a value")),
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Column::Int32(builder) => Arc::new(builder.finish()),
+            Column::Int64(builder) => Arc::new(builder.finish()),
+            Column::Float64(builder) => Arc::new(builder.finish()),
+            Column::Boolean(builder) => Arc::new(builder.finish()),
+            Column::Utf8(builder) => Arc::new(builder.finish()),
+            Column::Timestamp(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+/// Read a database table without its `fid`, in the order of the rows.
+fn read_table(database: &Database, table: &Schema, dictionary: bool) -> Result<RecordBatch, Error> {
+    let name = &table.table_name();
+    let (names, declared): (Vec<String>, Vec<String>) = database
+        .columns(name)?
+        .into_iter()
+        .filter(|(column, _)| column != "fid")
+        .unzip();
+    let mut columns: Vec<Column> = names
+        .iter()
+        .zip(&declared)
+        .map(|(column, declared)| {
+            Column::new(
+                table.column(column).map(|column| column.column_type),
+                declared,
+            )
+        })
+        .collect();
+
+    let quoted: Vec<String> = names.iter().map(|column| quote(column)).collect();
+    let sql = format!(
+        "SELECT {} FROM {} ORDER BY rowid",
+        quoted.join(", "),
+        quote(name)
+    );
+    let connection = database.connection();
+    let mut statement = connection
+        .prepare(&sql)
+        .map_err(|source| database.error(source))?;
+    let mut rows = statement
+        .query([])
+        .map_err(|source| database.error(source))?;
+    let mut row_number = 0;
+    while let Some(row) = rows.next().map_err(|source| database.error(source))? {
+        row_number += 1;
+        for (i, column) in columns.iter_mut().enumerate() {
+            let value = row.get_ref(i).map_err(|source| database.error(source))?;
+            column
+                .append(value)
+                .map_err(|message| Error::InvalidTable {
+                    table: name.to_string(),
+                    message: format!("row {row_number}, column {}: {message}", names[i]),
+                })?;
+        }
+    }
+
+    let mut fields = Vec::new();
+    let mut arrays = Vec::new();
+    for (column, builder) in names.iter().zip(&mut columns) {
+        let mut array = builder.finish();
+        if dictionary && array.data_type() == &DataType::Utf8 && repeats(&array) {
+            array = cast(
+                &array,
+                &DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+            )
+            .expect("text casts to a dictionary");
+        }
+        fields.push(Field::new(column, array.data_type().clone(), true));
+        arrays.push(array);
+    }
+    RecordBatch::try_new(Arc::new(ArrowSchema::new(fields)), arrays).map_err(|source| {
+        Error::InvalidTable {
+            table: name.to_string(),
+            message: source.to_string(),
+        }
+    })
+}
+
+/// Whether the values of a text column are on average used at least twice.
+fn repeats(array: &ArrayRef) -> bool {
+    let strings = array
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("the column is text");
+    let distinct: HashSet<Option<&str>> = strings.iter().collect();
+    !strings.is_empty() && distinct.len() * 2 <= strings.len()
+}
+
+/// The compression of an Arrow file, as found in its first record batch.
+pub fn compression(path: &Path) -> Result<ArrowCompression, Box<dyn StdError + Send + Sync>> {
+    let bytes = fs::read(path)?;
+    let invalid = || format!("{} is not an Arrow IPC file", path.display());
+    // The file ends with the footer, its length, and the magic bytes ARROW1
+    let end = bytes.len().checked_sub(10).ok_or_else(invalid)?;
+    let length = i32::from_le_bytes(bytes[end..end + 4].try_into()?);
+    let start = end
+        .checked_sub(usize::try_from(length)?)
+        .ok_or_else(invalid)?;
+    let footer = root_as_footer(&bytes[start..end]).map_err(|err| err.to_string())?;
+    let Some(block) = footer
+        .recordBatches()
+        .and_then(|blocks| blocks.iter().next())
+    else {
+        return Ok(ArrowCompression::None);
+    };
+    // A message is a continuation marker, its length, and the message itself
+    let mut start = usize::try_from(block.offset())?;
+    if bytes.get(start..start + 4) == Some(&[0xff; 4]) {
+        start += 4;
+    }
+    let length = bytes.get(start..start + 4).ok_or_else(invalid)?;
+    let length = usize::try_from(i32::from_le_bytes(length.try_into()?))?;
+    let message = bytes
+        .get(start + 4..start + 4 + length)
+        .ok_or_else(invalid)?;
+    let message = root_as_message(message).map_err(|err| err.to_string())?;
+    let codec = message
+        .header_as_record_batch()
+        .and_then(|batch| batch.compression())
+        .map(|compression| compression.codec());
+    Ok(match codec {
+        Some(CompressionType::LZ4_FRAME) => ArrowCompression::Lz4,
+        Some(CompressionType::ZSTD) => ArrowCompression::Zstd,
+        _ => ArrowCompression::None,
+    })
+}
+
+/// The SQLite type of an Arrow column, following how pandas writes the tables.
+pub fn sql_type(data_type: &DataType) -> Option<&'static str> {
+    match data_type {
+        DataType::Boolean
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64 => Some("INTEGER"),
+        DataType::Float16 | DataType::Float32 | DataType::Float64 => Some("REAL"),
+        DataType::Utf8 | DataType::LargeUtf8 => Some("TEXT"),
+        DataType::Dictionary(_, value) => sql_type(value).filter(|sql| *sql == "TEXT"),
+        DataType::Timestamp(_, _) | DataType::Date32 | DataType::Date64 => Some("TIMESTAMP"),
+        _ => None,
+    }
+}
+
+/// The values of an Arrow column as SQLite values.
+fn sql_values(array: &ArrayRef, sql_type: &str) -> Result<Vec<Value>, arrow::error::ArrowError> {
+    Ok(match sql_type {
+        "INTEGER" => cast(array, &DataType::Int64)?
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .expect("cast to Int64")
+            .iter()
+            .map(|value| value.map_or(Value::Null, Value::Integer))
+            .collect(),
+        "REAL" => cast(array, &DataType::Float64)?
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .expect("cast to Float64")
+            .iter()
+            .map(|value| value.map_or(Value::Null, Value::Real))
+            .collect(),
+        "TEXT" => cast(array, &DataType::Utf8)?
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .expect("cast to Utf8")
+            .iter()
+            .map(|value| value.map_or(Value::Null, |text| Value::Text(text.to_string())))
+            .collect(),
+        _ => cast(array, &DataType::Timestamp(TimeUnit::Millisecond, None))?
+            .as_any()
+            .downcast_ref::<TimestampMillisecondArray>()
+            .expect("cast to timestamps")
+            .iter()
+            .map(|value| {
+                value
+                    .and_then(DateTime::from_timestamp_millis)
+                    // The format that pandas writes
+                    .map_or(Value::Null, |time| {
+                        Value::Text(time.format("%Y-%m-%d %H:%M:%S%.6f").to_string())
+                    })
+            })
+            .collect(),
+    })
+}
+
+/// Create a table with an `fid` primary key like the Python package does, and register
+/// it in `gpkg_contents`.
+fn write_table(database: &Database, name: &str, batch: &RecordBatch) -> Result<(), Error> {
+    let invalid = |message: String| Error::InvalidTable {
+        table: name.to_string(),
+        message,
+    };
+    let schema = batch.schema();
+    let mut definitions = vec!["fid INTEGER PRIMARY KEY AUTOINCREMENT".to_string()];
+    let mut values = Vec::new();
+    for (field, array) in schema.fields().iter().zip(batch.columns()) {
+        let sql_type = sql_type(field.data_type()).ok_or_else(|| {
+            invalid(format!(
+                "column {} has unsupported type {}",
+                field.name(),
+                field.data_type()
+            ))
+        })?;
+        definitions.push(format!("{} {sql_type}", quote(field.name())));
+        values.push(sql_values(array, sql_type).map_err(|source| invalid(source.to_string()))?);
+    }
+    let columns: Vec<String> = schema
+        .fields()
+        .iter()
+        .map(|field| quote(field.name()))
+        .collect();
+    let placeholders = vec!["?"; columns.len()].join(", ");
+
+    let write_error = |source: rusqlite::Error| Error::Write {
+        path: database.path.clone(),
+        source: source.into(),
+    };
+    let has_contents = database.has_table("gpkg_contents")?;
+    let transaction = database
+        .connection()
+        .unchecked_transaction()
+        .map_err(write_error)?;
+    transaction
+        .execute(
+            &format!("CREATE TABLE {} ({})", quote(name), definitions.join(", ")),
+            [],
+        )
+        .map_err(write_error)?;
+    {
+        let mut insert = transaction
+            .prepare(&format!(
+                "INSERT INTO {} ({}) VALUES ({placeholders})",
+                quote(name),
+                columns.join(", ")
+            ))
+            .map_err(write_error)?;
+        for row in 0..batch.num_rows() {
+            insert
+                .execute(rusqlite::params_from_iter(
+                    values.iter().map(|column| &column[row]),
+                ))
+                .map_err(write_error)?;
+        }
+    }
+    if has_contents {
+        transaction
+            .execute(
+                "INSERT INTO gpkg_contents (table_name, data_type, identifier) VALUES (?1, 'attributes', ?1)",
+                [name],
+            )
+            .map_err(write_error)?;
+    }
+    transaction.commit().map_err(write_error)
+}
@@ -0,0 +1,92 @@
+//! Offline checks of a model, that can run without starting Julia.
+
+use std::path::Path;
+
+use ribasim::{
+    config::Config,
+    error::Error,
+    geopackage::Database,
+    results::read_schema,
+    schema::{self, Schema, StoredType},
+};
+
+/// Check a parsed configuration and the files it refers to, returning all errors found.
+pub fn validate(config: &Config) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    let db_path = config.database_path();
+    if !db_path.is_file() {
+        errors.push(format!("Database file not found: {}", db_path.display()));
+    } else if let Err(err) = check_database(config, &mut errors) {
+        errors.push(err.to_string());
+    }
+
+    for (node, paths) in &config.tables {
+        for (kind, path) in paths {
+            let table_path = config.input_path(path);
+            if !table_path.is_file() {
+                errors.push(format!(
+                    "{node}.{kind}: Arrow file not found: {}",
+                    table_path.display()
+                ));
+                continue;
+            }
+            let schema =
+                schema::find(&format!("{node}.{kind}")).expect("TOML tables have a schema");
+            match check_arrow(schema, &table_path) {
+                Ok(problems) => {
+                    errors.extend(problems.into_iter().map(|problem| {
+                        format!("{node}.{kind} ({}): {problem}", table_path.display())
+                    }))
+                }
+                Err(err) => errors.push(err.to_string()),
+            }
+        }
+    }
+
+    errors
+}
+
+/// Check the columns of the input tables in the database against their schema, leaving
+/// out tables that are read from an Arrow file instead.
+fn check_database(config: &Config, errors: &mut Vec<String>) -> Result<(), Error> {
+    let database = Database::open(&config.database_path())?;
+    for name in database.tables()? {
+        let Some(schema) = schema::find(&name) else {
+            continue;
+        };
+        if config
+            .tables
+            .get(schema.section)
+            .is_some_and(|paths| paths.contains_key(schema.kind))
+        {
+            continue;
+        }
+        let columns: Vec<(String, StoredType)> = database
+            .columns(&name)?
+            .into_iter()
+            .map(|(column, declared)| (column, StoredType::from_sqlite(&declared)))
+            .collect();
+        errors.extend(
+            schema
+                .check(&columns)
+                .into_iter()
+                .map(|problem| format!("{name}: {problem}")),
+        );
+    }
+    Ok(())
+}
+
+fn check_arrow(schema: &Schema, path: &Path) -> Result<Vec<String>, Error> {
+    let columns: Vec<(String, StoredType)> = read_schema(path)?
+        .fields()
+        .iter()
+        .map(|field| {
+            (
+                field.name().clone(),
+                StoredType::from_arrow(field.data_type()),
+            )
+        })
+        .collect();
+    Ok(schema.check(&columns))
+}
@@ -1,9 +1,24 @@
+import difflib
+import json
+import os
+import signal
+import sqlite3
 import subprocess
+import zipfile
+from contextlib import closing
+from datetime import datetime
 from pathlib import Path
+from xml.etree import ElementTree
 
+import pandas as pd
+import pyarrow as pa
 import pytest
 import ribasim
 import ribasim_testmodels
+from pyarrow import feather
+from ribasim.config import Node
+from ribasim.nodes import discrete_control
+from shapely.geometry import Point
 
 executable = Path(__file__).parents[1] / "ribasim" / "ribasim"
 
@@ -25,6 +40,985 @@ def test_models(model_constructor, tmp_path):
         assert result.returncode == 0
 
 
+# The invalid test models have tables of the right shape, their problems are only found
+# by the Julia core, or by `ribasim check` for those in the network
+NOT_CAUGHT_BY_VALIDATE = {
+    "invalid_qh": "Q(h) values are checked by the core",
+    "invalid_fractional_flow": "fractions and neighbors are checked by ribasim check",
+    "invalid_discrete_control": "control states and look_ahead are checked by the core",
+    "invalid_edge_types": "edge types are checked by ribasim check",
+    "invalid_unstable": "the model only fails during the simulation",
+}
+
+
+@pytest.mark.parametrize(
+    "model_constructor",
+    [
+        pytest.param(
+            constructor,
+            marks=pytest.mark.xfail(reason=NOT_CAUGHT_BY_VALIDATE[name], strict=True),
+        )
+        if name in NOT_CAUGHT_BY_VALIDATE
+        else constructor
+        for name, constructor in ribasim_testmodels.constructors.items()
+    ],
+)
+def test_validate(model_constructor, tmp_path):
+    model = model_constructor()
+    model.write(tmp_path / "ribasim.toml")
+
+    result = subprocess.run([executable, "validate", tmp_path / "ribasim.toml"])
+
+    if model_constructor.__name__.startswith("invalid_"):
+        assert result.returncode == 3
+    else:
+        assert result.returncode == 0
+
+
+def test_validate_invalid_model(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+
+    result = subprocess.run(
+        [executable, "validate", toml_path]
+        + ["--set", "solver.algorithm=QNDFF"]
+        + ["--set", "solver.saveat=1.5"]
+        + ["--set", "solver.dt=-1"],
+        capture_output=True,
+        text=True,
+    )
+
+    assert result.returncode == 3
+    assert "solver.algorithm" in result.stderr
+    assert "solver.saveat" in result.stderr
+    assert "solver.dt" in result.stderr
+    assert "Found 3 error(s)" in result.stderr
+
+    subprocess.run(
+        [executable, "tables", "extract", toml_path, "basin.profile"], check=True
+    )
+    (tmp_path / "basin" / "profile.arrow").unlink()
+    with closing(sqlite3.connect(tmp_path / "database.gpkg")) as connection:
+        connection.execute('ALTER TABLE "Basin / state" ADD COLUMN depth REAL')
+        connection.commit()
+
+    result = subprocess.run(
+        [executable, "validate", toml_path], capture_output=True, text=True
+    )
+
+    assert result.returncode == 3
+    assert "basin.profile: Arrow file not found" in result.stderr
+    assert "Basin / state: column depth is not in the schema" in result.stderr
+    assert "Found 2 error(s)" in result.stderr
+
+
+def test_validate_invalid_toml(tmp_path):
+    toml_path = tmp_path / "ribasim.toml"
+    toml_path.write_text('starttime = 2020-01-01\n[solver]\nalgorithm = "QNDFF"\n')
+
+    result = subprocess.run(
+        [executable, "validate", toml_path], capture_output=True, text=True
+    )
+
+    assert result.returncode != 0
+    assert "endtime: missing required key" in result.stderr
+    assert "solver.algorithm" in result.stderr
+
+
+def test_set_override(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    model.write(tmp_path / "ribasim.toml")
+
+    result = subprocess.run(
+        [
+            executable,
+            "validate",
+            tmp_path / "ribasim.toml",
+            "--set",
+            "solver.algorithm=Tsit5",
+        ]
+    )
+    assert result.returncode == 0
+
+    result = subprocess.run(
+        [executable, "validate", tmp_path / "ribasim.toml", "--set", "solver.dt=-1"],
+        capture_output=True,
+        text=True,
+    )
+    assert result.returncode != 0
+    assert "solver.dt" in result.stderr
+
+
+def test_stepwise(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    model.write(tmp_path / "ribasim.toml")
+
+    result = subprocess.run([executable, "run", tmp_path / "ribasim.toml", "--stepwise"])
+
+    assert result.returncode == 0
+    assert (tmp_path / "results" / "basin.arrow").exists()
+
+
+@pytest.mark.parametrize("signum", [signal.SIGINT, signal.SIGTERM])
+def test_stepwise_interrupt(signum, tmp_path):
+    model = ribasim_testmodels.basic_model()
+    # Long enough to still be running when it is interrupted
+    model.endtime = datetime(2200, 1, 1)
+    model.write(tmp_path / "ribasim.toml")
+
+    process = subprocess.Popen(
+        [executable, "run", tmp_path / "ribasim.toml"]
+        + ["--progress", "json", "--progress-interval", "0"],
+        stderr=subprocess.PIPE,
+        text=True,
+    )
+    # Wait for the first time step
+    for line in process.stderr:
+        if line.startswith("{"):
+            break
+    process.send_signal(signum)
+    process.communicate(timeout=600)
+
+    assert process.returncode == 130
+    basin = pd.read_feather(tmp_path / "results" / "basin.arrow")
+    assert basin["time"].max() < pd.Timestamp(model.endtime)
+
+
+def test_run_many(tmp_path):
+    ribasim_testmodels.basic_model().write(tmp_path / "basic" / "ribasim.toml")
+    ribasim_testmodels.bucket_model().write(tmp_path / "bucket" / "ribasim.toml")
+    ribasim_testmodels.invalid_edge_types_model().write(
+        tmp_path / "invalid" / "ribasim.toml"
+    )
+    (tmp_path / "models.txt").write_text(
+        "# models\nbasic/ribasim.toml\n\ninvalid/ribasim.toml\nmissing/ribasim.toml\n"
+    )
+
+    result = subprocess.run(
+        [
+            executable,
+            "run",
+            tmp_path / "bucket" / "ribasim.toml",
+            "--from-file",
+            tmp_path / "models.txt",
+        ],
+        capture_output=True,
+        text=True,
+    )
+
+    assert result.returncode == 1
+    assert "2 of 4 models succeeded" in result.stdout
+    assert "invalid model" in result.stdout
+    assert "not run" not in result.stdout
+    assert (tmp_path / "basic" / "results" / "basin.arrow").exists()
+    assert (tmp_path / "bucket" / "results" / "basin.arrow").exists()
+
+
+def test_progress_json(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    model.write(tmp_path / "ribasim.toml")
+
+    result = subprocess.run(
+        [executable, "run", tmp_path / "ribasim.toml", "--progress", "json"],
+        capture_output=True,
+        text=True,
+    )
+
+    assert result.returncode == 0
+    records = [
+        json.loads(line) for line in result.stderr.splitlines() if line.startswith("{")
+    ]
+    assert records[-1]["fraction"] == 1.0
+
+
+def test_bmi_script(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    model.write(tmp_path / "ribasim.toml")
+
+    result = subprocess.run(
+        [executable, "bmi", tmp_path / "ribasim.toml"],
+        input="step\nget basin.level --node 1\ntime\nfinalize\n",
+        capture_output=True,
+        text=True,
+    )
+
+    assert result.returncode == 0
+    assert "Basin #1" in result.stdout
+
+
+def test_bmi_values(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+    with closing(sqlite3.connect(tmp_path / "database.gpkg")) as connection:
+        (n_basins,) = connection.execute(
+            "SELECT count(*) FROM Node WHERE node_type = 'Basin'"
+        ).fetchone()
+
+    result = subprocess.run(
+        [executable, "bmi", toml_path],
+        input="step\nshape basin.storage\nget basin.storage\nfinalize\n",
+        capture_output=True,
+        text=True,
+    )
+
+    assert result.returncode == 0
+    assert f"[{n_basins}]" in result.stdout
+    values = [line for line in result.stdout.splitlines() if line.startswith("Basin #")]
+    assert len(values) == n_basins
+
+    (tmp_path / "results" / "basin.arrow").unlink()
+    result = subprocess.run(
+        [executable, "bmi", toml_path],
+        input="step\nget basin.nonsense\n",
+        capture_output=True,
+        text=True,
+    )
+
+    # A script stops at the first error, with the message of the core
+    assert result.returncode == 1
+    assert "BMI function `get_var_type` failed" in result.stderr
+    assert "Unknown variable basin.nonsense" in result.stderr
+    # Dropping the model finalizes it, which writes the results
+    assert (tmp_path / "results" / "basin.arrow").exists()
+
+
+def test_results_summary(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    model.write(tmp_path / "ribasim.toml")
+    subprocess.run([executable, tmp_path / "ribasim.toml"], check=True)
+
+    result = subprocess.run(
+        [executable, "results", "summary", tmp_path / "ribasim.toml", "--format", "json"],
+        check=True,
+        capture_output=True,
+        text=True,
+    )
+
+    summary = json.loads(result.stdout)
+    assert len(summary["basins"]) == len(model.basin.node.df)
+    assert summary["worst_relative_error"] is not None
+
+
+def test_results_allocation(tmp_path):
+    model = ribasim_testmodels.user_demand_model()
+    model.write(tmp_path / "ribasim.toml")
+    subprocess.run([executable, tmp_path / "ribasim.toml"], check=True)
+
+    result = subprocess.run(
+        [
+            executable,
+            "results",
+            "allocation",
+            tmp_path / "ribasim.toml",
+            "--format",
+            "json",
+        ],
+        check=True,
+        capture_output=True,
+        text=True,
+    )
+    report = json.loads(result.stdout)
+
+    # The demand at a row holds for the interval until the next row, and the realized
+    # flow of the next row is the mean over that interval
+    df = pd.read_feather(tmp_path / "results" / "allocation.arrow")
+    df = df[df["node_type"] == "UserDemand"]
+    keys = ["subnetwork_id", "node_type", "node_id", "priority"]
+    shortages = [d for d in report["demands"] if d["node_type"] == "UserDemand"]
+    assert len(shortages) == len(df.groupby(keys))
+    for shortage in shortages:
+        group = df[
+            (df["node_id"] == shortage["node_id"])
+            & (df["priority"] == shortage["priority"])
+        ].sort_values("time")
+        dt = group["time"].diff().dt.total_seconds().shift(-1)
+        demand = group["demand"].clip(lower=0.0)
+        short = demand - group["realized"].shift(-1)
+        is_short = short > 1e-6 * demand
+        assert shortage["demand"] == pytest.approx((demand * dt).sum())
+        assert shortage["shortage"] == pytest.approx((short * dt)[is_short].sum())
+
+
+def test_results_control(tmp_path):
+    model = ribasim_testmodels.pump_discrete_control_model()
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+    subprocess.run([executable, toml_path], check=True)
+
+    def control(*args):
+        result = subprocess.run(
+            [executable, "results", "control", toml_path, "--format", "json", *args],
+            check=True,
+            capture_output=True,
+            text=True,
+        )
+        report = json.loads(result.stdout)
+        return {node["control_node_id"]: node for node in report["nodes"]}
+
+    nodes = control()
+    df = pd.read_feather(tmp_path / "results" / "control.arrow")
+    assert set(nodes) == set(df["control_node_id"])
+    # A control state holds until the next row of the node, or the end of the simulation
+    for node_id, group in df.groupby("control_node_id"):
+        group = group.sort_values("time")
+        end = group["time"].shift(-1).fillna(pd.Timestamp(model.endtime))
+        seconds = (end - group["time"]).dt.total_seconds()
+        durations = seconds.groupby(group["control_state"]).sum()
+        state = group["control_state"]
+        switches = (state != state.shift()).sum() - 1
+        assert nodes[node_id]["switches"] == switches
+        assert nodes[node_id]["durations"] == pytest.approx(durations.to_dict())
+    assert any(node["switches"] > 0 for node in nodes.values())
+
+    # With a window over the whole simulation, any switch is too many
+    chattering = control("--window", str(366 * 86400), "--max-switches", "0")
+    for node_id, node in chattering.items():
+        if node["switches"] == 0:
+            assert node["chattering"] is None
+        else:
+            assert node["chattering"]["switches"] == nodes[node_id]["switches"]
+    result = subprocess.run(
+        [executable, "results", "control", toml_path, "--max-switches", "0"],
+        check=True,
+        capture_output=True,
+        text=True,
+    )
+    assert "switch more than 0 times" in result.stdout
+
+
+def test_balance_gate(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    model.write(tmp_path / "ribasim.toml")
+
+    result = subprocess.run(
+        [executable, tmp_path / "ribasim.toml", "--max-relative-error", "1.0"]
+    )
+
+    assert result.returncode == 0
+
+    result = subprocess.run(
+        [executable, tmp_path / "ribasim.toml", "--max-relative-error", "1e-12"],
+        capture_output=True,
+        text=True,
+    )
+
+    assert result.returncode == 7
+    assert "exceeds the limit" in result.stderr
+    basin = pd.read_feather(tmp_path / "results" / "basin.arrow")
+    relative_error = basin["relative_error"]
+    exceeding = relative_error.isna() | (relative_error.abs() > 1e-12)
+    # The table rows are like `2020-01-01 00:00:00  1  0.5  1e-9`
+    rows = [line.split() for line in result.stderr.splitlines()]
+    header = rows.index(["time", "node_id", "balance_error", "relative_error"])
+    listed = {
+        int(row[2]) for row in rows[header + 1 :] if len(row) > 2 and row[2].isdigit()
+    }
+    assert listed
+    assert listed <= set(basin.loc[exceeding, "node_id"])
+
+
+@pytest.mark.parametrize("format", ["csv", "parquet", "jsonl"])
+def test_results_export(format, tmp_path):
+    model = ribasim_testmodels.basic_model()
+    model.write(tmp_path / "ribasim.toml")
+    subprocess.run([executable, tmp_path / "ribasim.toml"], check=True)
+
+    subprocess.run(
+        [
+            executable,
+            "results",
+            "export",
+            tmp_path / "ribasim.toml",
+            "--format",
+            format,
+            "--tables",
+            "basin,flow",
+            "--output",
+            tmp_path / "export",
+        ],
+        check=True,
+    )
+
+    assert (tmp_path / "export" / f"basin.{format}").exists()
+    assert (tmp_path / "export" / f"flow.{format}").exists()
+
+
+def test_compare(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    model.write(tmp_path / "a" / "ribasim.toml")
+    model.write(tmp_path / "b" / "ribasim.toml")
+    subprocess.run([executable, tmp_path / "a" / "ribasim.toml"], check=True)
+    subprocess.run([executable, tmp_path / "b" / "ribasim.toml"], check=True)
+
+    def compare(*args):
+        return subprocess.run(
+            [
+                executable,
+                "compare",
+                tmp_path / "a" / "results",
+                tmp_path / "b" / "results",
+                *args,
+            ],
+            capture_output=True,
+            text=True,
+        )
+
+    def flow_table(result):
+        comparison = json.loads(result.stdout)
+        return next(table for table in comparison["tables"] if table["name"] == "flow")
+
+    assert compare().returncode == 0
+
+    flow_path = tmp_path / "b" / "results" / "flow.arrow"
+    table = feather.read_table(flow_path)
+    flow = table.to_pandas()
+
+    def write_flow(df):
+        df = pa.Table.from_pandas(df, schema=table.schema, preserve_index=False)
+        feather.write_feather(df, flow_path)
+
+    # Perturb the largest flow rate by a relative 1e-3
+    row = flow["flow_rate"].abs().idxmax()
+    perturbation = abs(flow.loc[row, "flow_rate"]) * 1e-3
+    flow.loc[row, "flow_rate"] *= 1.001
+    write_flow(flow)
+    result = compare("--format", "json")
+    assert result.returncode == 8
+    column = next(c for c in flow_table(result)["columns"] if c["name"] == "flow_rate")
+    assert column["different"] == 1
+    assert compare("--atol", str(2 * perturbation)).returncode == 0
+    assert compare("--rtol", "1e-2").returncode == 0
+
+    write_flow(flow.drop(index=row))
+    result = compare("--rtol", "1e-2", "--format", "json")
+    assert result.returncode == 8
+    missing = flow_table(result)
+    assert missing["rows_only_in_a"] == 1
+    assert missing["rows_only_in_b"] == 0
+    assert len(missing["examples_only_in_a"]) == 1
+
+    flow_path.unlink()
+    result = compare("--format", "json")
+    assert result.returncode == 8
+    missing = flow_table(result)
+    assert missing["rows_a"] is not None
+    assert missing["rows_b"] is None
+
+
+def test_results_query(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+    subprocess.run([executable, toml_path], check=True)
+
+    result = subprocess.run(
+        [
+            executable,
+            "results",
+            "query",
+            "basin",
+            toml_path,
+            "--node",
+            "1",
+            "--var",
+            "level,storage",
+            "--format",
+            "json",
+        ],
+        check=True,
+        capture_output=True,
+        text=True,
+    )
+    rows = json.loads(result.stdout)
+    assert rows
+    assert {row["node_id"] for row in rows} == {1}
+    assert set(rows[0]) == {"time", "node_id", "level", "storage"}
+
+    result = subprocess.run(
+        [executable, "results", "query", "basin", toml_path, "--edge", "1"]
+    )
+    assert result.returncode == 2
+
+
+def test_results_aggregate(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    # Weekly rows straddle the month boundaries
+    model.solver.saveat = 7 * 86400
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+    subprocess.run([executable, toml_path], check=True)
+
+    def aggregate(period, stat, *args):
+        subprocess.run(
+            [executable, "results", "aggregate", toml_path, "--period", period]
+            + ["--stat", stat, *args],
+            check=True,
+        )
+        name = f"basin_{period.replace('-', '_')}_{stat}.arrow"
+        return pd.read_feather(tmp_path / "results" / name)
+
+    basin = pd.read_feather(tmp_path / "results" / "basin.arrow")
+    endtime = pd.Timestamp(model.endtime)
+    months = pd.date_range(pd.Timestamp(model.starttime), endtime, freq="MS")
+
+    # A rate holds from its time stamp until the next one, and is split over the months
+    end = basin.groupby("node_id")["time"].shift(-1).fillna(endtime)
+    parts = []
+    for start, stop in zip(months[:-1], months[1:]):
+        overlap = end.clip(upper=stop) - basin["time"].clip(lower=start)
+        seconds = overlap.dt.total_seconds()
+        inside = seconds > 0
+        part = basin.loc[inside, ["node_id"]].assign(
+            time=start,
+            storage_change=basin["storage_rate"][inside] * seconds[inside],
+            precipitation_volume=basin["precipitation"][inside] * seconds[inside],
+        )
+        parts.append(part.groupby(["time", "node_id"], as_index=False).sum())
+    expected = pd.concat(parts).sort_values(["time", "node_id"], ignore_index=True)
+
+    volumes = aggregate("month", "sum")
+    # A row at the endtime lasts no time, and has no volumes in the month it starts
+    volumes = volumes[volumes["time"] < endtime]
+    volumes = volumes.sort_values(["time", "node_id"], ignore_index=True)
+    assert "storage_change" in volumes
+    assert "storage" not in volumes
+    assert "level" not in volumes
+    assert list(volumes["time"]) == list(expected["time"])
+    assert list(volumes["node_id"]) == list(expected["node_id"])
+    for column in ["storage_change", "precipitation_volume"]:
+        assert volumes[column].to_numpy() == pytest.approx(
+            expected[column].to_numpy(), rel=1e-9, abs=1e-6
+        )
+
+    # States are sampled at their time stamp, in the hydrological year containing it
+    means = aggregate("hydro-year", "mean", "--hydro-year-start", "4")
+    hydro_year = basin["time"].dt.year - (basin["time"].dt.month < 4)
+    expected = basin.groupby([hydro_year, "node_id"])["storage"].mean()
+    assert set(means["time"]) == {pd.Timestamp(2019, 4, 1), pd.Timestamp(2020, 4, 1)}
+    for row in means.itertuples():
+        assert row.storage == pytest.approx(expected[(row.time.year, row.node_id)])
+
+
+def test_check(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+    subprocess.run([executable, "check", toml_path], check=True)
+
+    with closing(sqlite3.connect(tmp_path / "database.gpkg")) as connection:
+        connection.execute("UPDATE Edge SET edge_type = 'spill' WHERE fid = 1")
+        connection.commit()
+    result = subprocess.run(
+        [executable, "check", toml_path, "--format", "json"],
+        capture_output=True,
+        text=True,
+    )
+    assert result.returncode == 3
+    checks = {violation["check"] for violation in json.loads(result.stdout)}
+    assert "valid_edge_types" in checks
+
+
+@pytest.mark.parametrize(
+    "model_constructor, check",
+    [
+        (ribasim_testmodels.invalid_edge_types_model, "valid_edge_types"),
+        (ribasim_testmodels.invalid_fractional_flow_model, "valid_fractional_flow"),
+    ],
+)
+def test_check_invalid(model_constructor, check, tmp_path):
+    model = model_constructor()
+    model.write(tmp_path / "ribasim.toml")
+
+    result = subprocess.run(
+        [executable, "check", tmp_path / "ribasim.toml", "--format", "json"],
+        capture_output=True,
+        text=True,
+    )
+
+    assert result.returncode == 3
+    assert check in {violation["check"] for violation in json.loads(result.stdout)}
+
+
+def test_graph_export(tmp_path):
+    model = ribasim_testmodels.pump_discrete_control_model()
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+    with closing(sqlite3.connect(tmp_path / "database.gpkg")) as connection:
+        connection.execute(
+            "UPDATE Node SET subnetwork_id = 1 WHERE node_id IN (1, 2, 3)"
+        )
+        connection.commit()
+        edge = pd.read_sql("SELECT * FROM Edge", connection)
+        node_count = connection.execute("SELECT count(*) FROM Node").fetchone()[0]
+
+    namespace = {"g": "http://graphml.graphdrawing.org/xmlns"}
+
+    def export(*args):
+        result = subprocess.run(
+            [executable, "graph", "export", toml_path, "--format", "graphml", *args],
+            check=True,
+            capture_output=True,
+            text=True,
+        )
+        graph = ElementTree.fromstring(result.stdout)
+        node_ids = {
+            int(data.text)
+            for data in graph.findall(".//g:node/g:data[@key='node_id']", namespace)
+        }
+        return node_ids, len(graph.findall(".//g:edge", namespace))
+
+    node_ids, edge_count = export()
+    assert len(node_ids) == node_count
+    assert edge_count == len(edge)
+
+    result = subprocess.run(
+        [executable, "graph", "export", toml_path, "--format", "dot"],
+        check=True,
+        capture_output=True,
+        text=True,
+    )
+    control_edges = (edge["edge_type"] == "control").sum()
+    assert control_edges > 0
+    assert result.stdout.count("style=dashed") == control_edges
+
+    node_ids, edge_count = export("--subnetwork", "1")
+    assert node_ids == {1, 2, 3}
+    inside = edge["from_node_id"].isin(node_ids) & edge["to_node_id"].isin(node_ids)
+    assert edge_count == inside.sum()
+
+    # One hop follows the edges in either direction
+    neighbors = {1}
+    neighbors |= set(edge.loc[edge["from_node_id"] == 1, "to_node_id"])
+    neighbors |= set(edge.loc[edge["to_node_id"] == 1, "from_node_id"])
+    node_ids, _ = export("--around", "1")
+    assert node_ids == neighbors
+
+
+def test_coverage(tmp_path):
+    model = ribasim_testmodels.flow_boundary_time_model()
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+
+    result = subprocess.run(
+        [executable, "coverage", toml_path, "--format", "json"],
+        capture_output=True,
+        text=True,
+    )
+    assert result.returncode == 3
+    report = json.loads(result.stdout)
+    [series] = report["series"]
+    assert series["table"] == "FlowBoundary / time"
+    assert series["node_id"] == 1
+    assert series["starts_late"]
+    assert series["ends_early"]
+
+
+def test_info(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+
+    result = subprocess.run(
+        [executable, "info", toml_path, "--format", "json"],
+        check=True,
+        capture_output=True,
+        text=True,
+    )
+    info = json.loads(result.stdout)
+    assert info["nodes"]["Basin"] == len(model.basin.node.df)
+    assert info["edges"]["flow"] > 0
+
+
+def test_tables(tmp_path):
+    model = ribasim_testmodels.basic_transient_model()
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+    original = toml_path.read_text()
+
+    def read_profile():
+        with closing(sqlite3.connect(tmp_path / "database.gpkg")) as connection:
+            info = connection.execute('PRAGMA table_info("Basin / profile")')
+            types = {column[1]: column[2] for column in info.fetchall()}
+            df = pd.read_sql(
+                'SELECT node_id, area, level FROM "Basin / profile"', connection
+            )
+        return df, types
+
+    profile, types = read_profile()
+
+    subprocess.run(
+        [
+            executable,
+            "tables",
+            "extract",
+            toml_path,
+            "basin.profile",
+            "--compression",
+            "zstd",
+        ],
+        check=True,
+    )
+    assert (tmp_path / "basin" / "profile.arrow").is_file()
+    # Only the reference to the Arrow file is added to the TOML
+    diff = difflib.ndiff(original.splitlines(), toml_path.read_text().splitlines())
+    changes = [line for line in diff if line.startswith(("+ ", "- "))]
+    assert set(changes) <= {"+ [basin]", '+ profile = "basin/profile.arrow"'}
+    assert '+ profile = "basin/profile.arrow"' in changes
+    with closing(sqlite3.connect(tmp_path / "database.gpkg")) as connection:
+        tables = connection.execute("SELECT name FROM sqlite_master").fetchall()
+    assert ("Basin / profile",) not in tables
+
+    subprocess.run(
+        [executable, "tables", "embed", toml_path, "basin.profile"], check=True
+    )
+    assert toml_path.read_text() == original
+    embedded, embedded_types = read_profile()
+    pd.testing.assert_frame_equal(embedded, profile)
+    assert embedded_types == types
+
+    # The forcing repeats enough for the buffers to be compressed
+    subprocess.run(
+        [
+            executable,
+            "tables",
+            "extract",
+            toml_path,
+            "basin.time",
+            "--compression",
+            "lz4",
+        ],
+        check=True,
+    )
+    data = (tmp_path / "basin" / "time.arrow").read_bytes()
+    lz4_frame, zstd_frame = b"\x04\x22\x4d\x18", b"\x28\xb5\x2f\xfd"
+    assert lz4_frame in data
+    assert zstd_frame not in data
+    time = feather.read_table(tmp_path / "basin" / "time.arrow").to_pandas()
+    assert len(time) == len(model.basin.time.df)
+
+    model = ribasim_testmodels.pump_discrete_control_model()
+    toml_path = tmp_path / "pump" / "ribasim.toml"
+    model.write(toml_path)
+    subprocess.run(
+        [
+            executable,
+            "tables",
+            "extract",
+            toml_path,
+            "discrete_control.variable",
+            "--dictionary",
+        ],
+        check=True,
+    )
+    variable_path = tmp_path / "pump" / "discrete_control" / "variable.arrow"
+    table = feather.read_table(variable_path)
+    for name in ["listen_node_type", "variable"]:
+        field = table.schema.field(name)
+        assert pa.types.is_dictionary(field.type)
+        assert pa.types.is_string(field.type.value_type)
+    assert pa.types.is_integer(table.schema.field("listen_node_id").type)
+    variable = table.to_pandas()
+    assert variable["listen_node_type"].astype(str).tolist() == ["Basin", "Basin"]
+
+
+def test_schema(tmp_path):
+    result = subprocess.run(
+        [executable, "schema", "show", "basin.profile", "--format", "json"],
+        check=True,
+        capture_output=True,
+        text=True,
+    )
+    schema = json.loads(result.stdout)
+    assert schema["table"] == "Basin / profile"
+    assert [column["name"] for column in schema["columns"]] == [
+        "node_id",
+        "area",
+        "level",
+    ]
+
+    model = ribasim_testmodels.basic_model()
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+    with closing(sqlite3.connect(tmp_path / "database.gpkg")) as connection:
+        connection.execute('ALTER TABLE "Basin / profile" ADD COLUMN meta_source TEXT')
+        connection.execute('ALTER TABLE "Basin / profile" ADD COLUMN source TEXT')
+        connection.commit()
+
+    result = subprocess.run(
+        [executable, "validate", toml_path], capture_output=True, text=True
+    )
+    assert result.returncode == 3
+    assert "column source is not in the schema" in result.stderr
+    assert "meta_source" not in result.stderr
+
+
+def test_pack(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    toml_path = tmp_path / "model" / "ribasim.toml"
+    model.write(toml_path)
+
+    archive = tmp_path / "model.zip"
+    subprocess.run([executable, "pack", toml_path, "-o", archive], check=True)
+    result = subprocess.run(
+        [executable, "unpack", archive, "-o", tmp_path / "unpacked"],
+        check=True,
+        capture_output=True,
+        text=True,
+    )
+    assert "Verified 2 files" in result.stdout
+
+    unpacked = tmp_path / "unpacked"
+    manifest = json.loads((unpacked / "manifest.json").read_text())
+    assert manifest["ribasim_version"] == ribasim.__version__
+    assert {file["path"] for file in manifest["files"]} == {
+        "ribasim.toml",
+        "input/database.gpkg",
+    }
+    assert (unpacked / "input" / "database.gpkg").is_file()
+    subprocess.run([executable, "validate", unpacked / "ribasim.toml"], check=True)
+
+    # The manifest must not point outside of the directory it is unpacked in
+    for i, outside in enumerate(["../model/ribasim.toml", str(toml_path)]):
+        tampered = dict(manifest, toml=outside)
+        tampered["files"] = [
+            dict(file, path=outside) if file["path"] == "ribasim.toml" else file
+            for file in manifest["files"]
+        ]
+        archive = tmp_path / f"tampered{i}.zip"
+        with zipfile.ZipFile(archive, "w") as zip:
+            for name in ["ribasim.toml", "input/database.gpkg"]:
+                zip.write(unpacked / name, name)
+            zip.writestr("manifest.json", json.dumps(tampered))
+        result = subprocess.run(
+            [executable, "unpack", archive, "-o", tmp_path / f"tampered{i}"],
+            capture_output=True,
+            text=True,
+        )
+        assert result.returncode == 3
+        assert outside in result.stderr
+        assert "outside of the target directory" in result.stderr
+
+
+def test_migrate(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+    with closing(sqlite3.connect(tmp_path / "database.gpkg")) as connection:
+        connection.execute(
+            "ALTER TABLE Node RENAME COLUMN subnetwork_id TO allocation_network_id"
+        )
+        connection.commit()
+    toml = toml_path.read_text()
+    toml = toml.replace(f'"{ribasim.__version__}"', '"2024.5.0"')
+    toml_path.write_text(toml)
+
+    result = subprocess.run(
+        [executable, "migrate", toml_path, "--dry-run"],
+        check=True,
+        capture_output=True,
+        text=True,
+    )
+    assert "+Node: column subnetwork_id" in result.stdout
+    assert toml_path.read_text() == toml
+
+    subprocess.run([executable, "migrate", toml_path], check=True)
+    assert (tmp_path / "database.gpkg.bak").is_file()
+    assert f'ribasim_version = "{ribasim.__version__}"' in toml_path.read_text()
+    subprocess.run([executable, "validate", toml_path], check=True)
+
+
+def test_migrate_node_type(tmp_path):
+    model = ribasim_testmodels.user_demand_model()
+    model.discrete_control.add(
+        Node(5, Point(0, 1)),
+        [
+            discrete_control.Variable(
+                listen_node_type="UserDemand",
+                listen_node_id=[2],
+                variable="flow_rate",
+                compound_variable_id=1,
+            ),
+            discrete_control.Condition(greater_than=[1e-4], compound_variable_id=1),
+            discrete_control.Logic(truth_state=["T", "F"], control_state=["on", "off"]),
+        ],
+    )
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+    subprocess.run(
+        [executable, "tables", "extract", toml_path, "user_demand.time"], check=True
+    )
+    # Bring the model back to before the node_id column and the UserDemand rename
+    database_path = tmp_path / "database.gpkg"
+    with closing(sqlite3.connect(database_path)) as connection:
+        connection.execute(
+            'ALTER TABLE "UserDemand / static" RENAME TO "User / static"'
+        )
+        connection.execute(
+            "UPDATE gpkg_contents SET table_name = 'User / static'"
+            " WHERE table_name = 'UserDemand / static'"
+        )
+        connection.execute(
+            "UPDATE Node SET node_type = 'User' WHERE node_type = 'UserDemand'"
+        )
+        connection.execute(
+            "UPDATE \"DiscreteControl / variable\" SET listen_node_type = 'User'"
+        )
+        connection.execute("ALTER TABLE Node DROP COLUMN node_id")
+        connection.commit()
+    subprocess.run(
+        [
+            executable,
+            "tables",
+            "extract",
+            toml_path,
+            "discrete_control.variable",
+            "--compression",
+            "zstd",
+            "--dictionary",
+        ],
+        check=True,
+    )
+    toml = toml_path.read_text()
+    toml = toml.replace("[user_demand]", "[user]")
+    toml = toml.replace(f'"{ribasim.__version__}"', '"2024.3.0"')
+    toml_path.write_text(toml)
+
+    subprocess.run([executable, "migrate", toml_path], check=True)
+
+    toml = toml_path.read_text()
+    assert "[user_demand]" in toml
+    assert "[user]" not in toml
+    with closing(sqlite3.connect(database_path)) as connection:
+        tables = {
+            name
+            for (name,) in connection.execute(
+                "SELECT name FROM sqlite_master WHERE type = 'table'"
+            )
+        }
+        contents = {
+            name
+            for (name,) in connection.execute("SELECT table_name FROM gpkg_contents")
+        }
+        node = pd.read_sql("SELECT * FROM Node", connection)
+    assert "UserDemand / static" in tables
+    assert "User / static" not in tables
+    assert "UserDemand / static" in contents
+    assert "User / static" not in contents
+    assert (node["node_id"] == node["fid"]).all()
+    assert set(node["node_type"]) == {
+        "Basin",
+        "UserDemand",
+        "Terminal",
+        "DiscreteControl",
+    }
+    variable = pd.read_feather(tmp_path / "discrete_control" / "variable.arrow")
+    assert list(variable["listen_node_type"]) == ["UserDemand"]
+    assert (tmp_path / "discrete_control" / "variable.arrow.bak").is_file()
+    assert (tmp_path / "user_demand" / "time.arrow").is_file()
+
+
 def test_version():
     result = subprocess.run(
         [executable, "--version"], check=True, capture_output=True, text=True
@@ -37,6 +1031,42 @@ def test_help():
     subprocess.run([executable, "--help"], check=True)
 
 
+def test_library_discovery(tmp_path):
+    model = ribasim_testmodels.basic_model()
+    toml_path = tmp_path / "ribasim.toml"
+    model.write(toml_path)
+    env = {key: value for key, value in os.environ.items() if key != "RIBASIM_LIB"}
+    lib = tmp_path / "lib" / "libribasim.so"
+    env_lib = tmp_path / "env" / "libribasim.so"
+
+    def run(*args, **extra_env):
+        return subprocess.run(
+            [executable, toml_path, *args],
+            env=env | extra_env,
+            capture_output=True,
+            text=True,
+        )
+
+    result = run("--lib", lib)
+    assert result.returncode == 4
+    assert str(lib) in result.stderr
+
+    result = run("--verbose", RIBASIM_LIB=str(env_lib))
+    assert result.returncode == 4
+    assert f"Looking for libribasim at {env_lib}: not found" in result.stderr
+
+    # --lib takes precedence over RIBASIM_LIB
+    result = run("--lib", lib, RIBASIM_LIB=str(env_lib))
+    assert result.returncode == 4
+    assert str(lib) in result.stderr
+    assert str(env_lib) not in result.stderr
+
+    # Without either, the library that is installed with the CLI is found
+    result = run("--verbose")
+    assert result.returncode == 0
+    assert ": found" in result.stderr
+
+
 def test_missing_toml():
     result = subprocess.run([executable, "/there/is/no/toml"])
-    assert result.returncode != 0
+    assert result.returncode == 3